@@ -0,0 +1,106 @@
+//! Per-project defaults for the command line, read from a TOML file.
+
+use crate::error::{Error, Result};
+use serde::Deserialize;
+use std::{
+  collections::HashMap,
+  env, fs,
+  path::{Path, PathBuf},
+};
+
+/// Environment variable naming the config file to read.
+const CONFIG_ENV: &str = "ATAR_CONFIG";
+
+/// Config file looked for in the current directory.
+const PROJECT_FILE: &str = ".atar.toml";
+
+/// Config file looked for under `$HOME`.
+const USER_FILE: &str = ".config/atar/config.toml";
+
+/// Defaults for command line flags, read from a TOML file such as:
+///
+/// ```toml
+/// terraform_path = "infra/main.tf"
+/// parallelism = 5
+/// min_version = "1.5.0"
+///
+/// [vars]
+/// region = "eu-west-1"
+/// ```
+///
+/// Flags given on the command line take precedence. Relative paths are
+/// resolved against the directory holding the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Config {
+  /// Terraform `main.tf` or its directory.
+  pub terraform_path: Option<PathBuf>,
+  /// Terraform variables, each overridden by a `--var` of the same name.
+  #[serde(default)]
+  pub vars: HashMap<String, String>,
+  /// `.tfvars` files, read before those passed with `--var-file`.
+  #[serde(default)]
+  pub var_files: Vec<PathBuf>,
+  /// Number of concurrent Terraform operations.
+  pub parallelism: Option<u32>,
+  /// Terraform binary name or path, used unless `--binary`, `--engine` or
+  /// `TERRAFORM_BINARY` chooses one.
+  pub binary: Option<PathBuf>,
+  /// Whether to log the Terraform commands run.
+  pub debug: Option<bool>,
+  /// Oldest Terraform version accepted.
+  pub min_version: Option<semver::Version>,
+}
+
+impl Config {
+  /// Path of the config file to use: `$ATAR_CONFIG` if set, else
+  /// `.atar.toml` in the current directory, else
+  /// `$HOME/.config/atar/config.toml`, or `None` when there is none.
+  ///
+  /// A path from `$ATAR_CONFIG` is returned even if missing, so that
+  /// [`Config::load`] reports it.
+  pub fn locate() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_ENV).filter(|p| !p.is_empty()) {
+      return Some(PathBuf::from(path));
+    }
+    let project = PathBuf::from(PROJECT_FILE);
+    if project.is_file() {
+      return Some(project);
+    }
+    env::var_os("HOME")
+      .map(|home| Path::new(&home).join(USER_FILE))
+      .filter(|user| user.is_file())
+  }
+
+  /// Read the config file at `path`.
+  ///
+  /// Unknown keys are rejected, so that a misspelled one is not silently
+  /// ignored.
+  pub fn load(path: &Path) -> Result<Config> {
+    let contents = fs::read_to_string(path).map_err(|err| {
+      Error::InvalidOptions(format!(
+        "Failed to read config file {}: {}",
+        path.display(),
+        err
+      ))
+    })?;
+    let mut config: Config = toml::from_str(&contents).map_err(|err| {
+      Error::InvalidOptions(format!("{}: {}", path.display(), err))
+    })?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    if let Some(file) = &mut config.terraform_path {
+      *file = dir.join(&*file);
+    }
+    for file in &mut config.var_files {
+      *file = dir.join(&*file);
+    }
+    // A bare name is looked up in PATH rather than next to the file.
+    if let Some(bin) = &mut config.binary {
+      if bin.components().count() > 1 {
+        *bin = dir.join(&*bin);
+      }
+    }
+    Ok(config)
+  }
+}
@@ -0,0 +1,174 @@
+//! Loading Terraform variables from `.env` files.
+
+use crate::error::{Error, Result};
+use std::{collections::HashMap, fs, path::PathBuf};
+
+/// Read every file in `paths` in order, later files overriding earlier
+/// ones, and collect the entries named like Terraform variables.
+///
+/// A `TF_VAR_` prefix on a key is dropped, so files written for
+/// Terraform's own environment variables work as they are.
+pub(crate) fn load(paths: &[PathBuf]) -> Result<HashMap<String, String>> {
+  let mut vars = HashMap::new();
+  for path in paths {
+    let contents = fs::read_to_string(path).map_err(|err| {
+      Error::InvalidOptions(format!(
+        "Failed to read env file {}: {}",
+        path.display(),
+        err
+      ))
+    })?;
+    let entries = parse(&contents).map_err(|(line, message)| {
+      Error::InvalidOptions(format!("{}:{}: {}", path.display(), line, message))
+    })?;
+    for (key, value) in entries {
+      let name = key.strip_prefix("TF_VAR_").unwrap_or(&key);
+      if is_variable_name(name) {
+        vars.insert(name.to_string(), value);
+      } else {
+        tracing::debug!("Skipping `{}` in {}", key, path.display());
+      }
+    }
+  }
+  Ok(vars)
+}
+
+/// Parse `KEY=VALUE` lines, skipping blank lines and `#` comments.
+///
+/// An optional `export ` prefix is ignored. Values may be wrapped in single
+/// or double quotes, which are removed with no escape processing;
+/// unquoted values end at a ` #` comment. Nothing is expanded. Errors carry
+/// the 1-based line number.
+fn parse(contents: &str) -> Result<Vec<(String, String)>, (usize, String)> {
+  let mut entries = Vec::new();
+  for (i, line) in contents.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let Some((key, value)) = line.split_once('=') else {
+      return Err((i + 1, "expected KEY=VALUE".to_string()));
+    };
+    let key = key.trim();
+    if key.is_empty() {
+      return Err((i + 1, "missing key before `=`".to_string()));
+    }
+    entries.push((key.to_string(), unquote(value.trim()).to_string()));
+  }
+  Ok(entries)
+}
+
+/// Strip matching surrounding quotes from `value`, along with a comment
+/// after the closing quote, or an inline comment from an unquoted value.
+fn unquote(value: &str) -> &str {
+  for quote in ['"', '\''] {
+    let Some(rest) = value.strip_prefix(quote) else {
+      continue;
+    };
+    let quoted = rest.match_indices(quote).find_map(|(end, _)| {
+      let after = rest[end + 1..].trim_start();
+      (after.is_empty() || after.starts_with('#')).then(|| &rest[..end])
+    });
+    if let Some(quoted) = quoted {
+      return quoted;
+    }
+  }
+  match value.find(" #") {
+    Some(comment) => value[..comment].trim_end(),
+    None => value,
+  }
+}
+
+/// Whether `name` is a valid Terraform variable name.
+fn is_variable_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars
+    .next()
+    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entries(contents: &str) -> Vec<(String, String)> {
+    parse(contents).unwrap()
+  }
+
+  fn pair(key: &str, value: &str) -> (String, String) {
+    (key.to_string(), value.to_string())
+  }
+
+  #[test]
+  fn skips_blank_lines_and_comments() {
+    let parsed = entries("\n# region=us\n  \nregion=eu\n   # x=y\n");
+    assert_eq!(parsed, [pair("region", "eu")]);
+  }
+
+  #[test]
+  fn drops_export_prefix_and_trims() {
+    let parsed = entries("export token = abc \n  exported=1");
+    assert_eq!(parsed, [pair("token", "abc"), pair("exported", "1")]);
+  }
+
+  #[test]
+  fn splits_on_the_first_equals_sign() {
+    assert_eq!(entries("url=a=b=c"), [pair("url", "a=b=c")]);
+    assert_eq!(entries("empty="), [pair("empty", "")]);
+  }
+
+  #[test]
+  fn removes_quotes_without_escapes_or_expansion() {
+    let parsed = entries("a=\"x y\"\nb='$HOME'\nc=\"line\\nbreak\"\nd=\"open");
+    let expected = [
+      pair("a", "x y"),
+      pair("b", "$HOME"),
+      pair("c", "line\\nbreak"),
+      pair("d", "\"open"),
+    ];
+    assert_eq!(parsed, expected);
+  }
+
+  #[test]
+  fn strips_inline_comments() {
+    let parsed = entries("a=1 # one\nb=2#two\nc=\"3 # three\" # c\nd='4'#");
+    let expected = [
+      pair("a", "1"),
+      pair("b", "2#two"),
+      pair("c", "3 # three"),
+      pair("d", "4"),
+    ];
+    assert_eq!(parsed, expected);
+  }
+
+  #[test]
+  fn reports_line_numbers() {
+    let err = parse("a=1\n\nnot a pair").unwrap_err();
+    assert_eq!(err.0, 3);
+    let err = parse("# comment\n=value").unwrap_err();
+    assert_eq!(err.0, 2);
+  }
+
+  #[test]
+  fn load_keeps_variable_names_and_lets_later_files_win() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.env");
+    let second = dir.path().join("second.env");
+    fs::write(&first, "TF_VAR_region=us\nzone=a\n1bad=x\nHOME.DIR=y").unwrap();
+    fs::write(&second, "region=eu").unwrap();
+    let vars = load(&[first, second]).unwrap();
+    let expected = HashMap::from([pair("region", "eu"), pair("zone", "a")]);
+    assert_eq!(vars, expected);
+  }
+
+  #[test]
+  fn load_reports_the_file_and_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".env");
+    fs::write(&path, "a=1\nb").unwrap();
+    let err = load(std::slice::from_ref(&path)).unwrap_err().to_string();
+    assert!(err.contains(&format!("{}:2:", path.display())), "{}", err);
+  }
+}
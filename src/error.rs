@@ -0,0 +1,221 @@
+//! Errors returned by every public function of the library.
+
+use crate::ValidationError;
+use std::{io, path::PathBuf, time::Duration};
+
+/// Result type used throughout the library.
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Alias of [`Error`], for code that imports it next to other error types.
+pub type AtarError = Error;
+
+/// Error returned by a deploy or destroy hook.
+pub type HookError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Everything that can go wrong while running Terraform.
+///
+/// Variants can be matched on to tell failures apart, e.g. to retry a
+/// [`Error::Timeout`] but report an [`Error::ApplyFailed`].
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+  /// No usable Terraform binary was found.
+  #[error("{binary} must be installed and in PATH")]
+  TerraformNotInstalled {
+    /// The binary that was looked for.
+    binary: String,
+  },
+  /// The installed Terraform is older than
+  /// [`crate::DeployOptions::min_terraform_version`].
+  #[error("Terraform {required} or newer is required, found {found}")]
+  TerraformVersionTooOld {
+    /// The minimum version that was asked for.
+    required: semver::Version,
+    /// The version `terraform -version` reported.
+    found: semver::Version,
+  },
+  /// `terraform init` exited with an error.
+  #[error(
+    "`terraform init` failed with exit code {exit_code}{}",
+    stderr_suffix(.stderr)
+  )]
+  InitFailed {
+    /// The last few KiB Terraform wrote to stderr.
+    stderr: String,
+    /// Exit code, or -1 if Terraform was killed by a signal.
+    exit_code: i32,
+  },
+  /// `terraform apply` exited with an error.
+  #[error(
+    "`terraform apply` failed with exit code {exit_code}{}",
+    stderr_suffix(.stderr)
+  )]
+  ApplyFailed {
+    /// The last few KiB Terraform wrote to stderr.
+    stderr: String,
+    /// Exit code, or -1 if Terraform was killed by a signal.
+    exit_code: i32,
+  },
+  /// `terraform destroy` exited with an error.
+  #[error(
+    "`terraform destroy` failed with exit code {exit_code}{}",
+    stderr_suffix(.stderr)
+  )]
+  DestroyFailed {
+    /// The last few KiB Terraform wrote to stderr.
+    stderr: String,
+    /// Exit code, or -1 if Terraform was killed by a signal.
+    exit_code: i32,
+  },
+  /// Any other Terraform command exited with an error.
+  #[error(
+    "`{command}` failed with exit code {exit_code}{}",
+    stderr_suffix(.stderr)
+  )]
+  CommandFailed {
+    /// The command that failed, e.g. `terraform plan`.
+    command: String,
+    /// The last few KiB Terraform wrote to stderr.
+    stderr: String,
+    /// Exit code, or -1 if Terraform was killed by a signal.
+    exit_code: i32,
+  },
+  /// A Terraform command could not be started or its output not read.
+  #[error("Failed to execute `{command}`: {source}")]
+  CommandIo {
+    /// The command that was being run.
+    command: String,
+    /// The underlying I/O error.
+    source: io::Error,
+  },
+  /// Terraform printed JSON that could not be parsed.
+  #[error("Failed to parse Terraform output: {0}")]
+  OutputParseFailed(#[from] serde_json::Error),
+  /// The outputs could not be written to
+  /// [`crate::DeployOptions::output_file`].
+  #[error("Failed to write outputs to {}: {source}", .path.display())]
+  OutputFileFailed {
+    /// The file that was being written.
+    path: PathBuf,
+    /// The underlying I/O error.
+    source: io::Error,
+  },
+  /// The work directory could not be set up or cleaned.
+  #[error("Failed to prepare work directory: {0}")]
+  WorkspacePrepFailed(io::Error),
+  /// The options contain a value Terraform would reject.
+  #[error("{0}")]
+  InvalidOptions(String),
+  /// Two options were set that cannot be used together.
+  #[error("Options `{first}` and `{second}` cannot be used together")]
+  MutuallyExclusiveFlags {
+    /// The first of the conflicting options.
+    first: &'static str,
+    /// The second of the conflicting options.
+    second: &'static str,
+  },
+  /// `terraform validate` rejected the configuration.
+  #[error(transparent)]
+  Validation(#[from] ValidationError),
+  /// A Terraform command was killed after running longer than allowed.
+  #[error(
+    "`{operation}` timed out and was killed after {:.1}s",
+    .elapsed.as_secs_f64()
+  )]
+  Timeout {
+    /// The command that timed out, e.g. `terraform apply`.
+    operation: String,
+    /// How long the command had been running when it was killed.
+    elapsed: Duration,
+  },
+  /// The state has no resource at the requested address.
+  #[error("Resource `{address}` not found in state")]
+  ResourceNotFound {
+    /// The resource address that was looked up.
+    address: String,
+  },
+  /// A directory was given as the configuration but holds no Terraform
+  /// files.
+  #[error("No Terraform files (*.tf, *.tf.json) found in {}", .dir.display())]
+  NoTerraformFiles {
+    /// The directory that was searched.
+    dir: PathBuf,
+  },
+  /// No work directory exists for the config, so it was never deployed or
+  /// has been cleaned since.
+  #[error(
+    "No work directory at {}; deploy the configuration first",
+    .work_dir.display()
+  )]
+  WorkDirMissing {
+    /// Where the work directory was expected.
+    work_dir: PathBuf,
+  },
+  /// Another process kept the work directory locked for longer than
+  /// allowed.
+  #[error(
+    "Work directory {} is locked by another process (waited {:.1}s)",
+    .work_dir.display(),
+    .timeout.as_secs_f64()
+  )]
+  WorkspaceLocked {
+    /// The work directory that could not be locked.
+    work_dir: PathBuf,
+    /// How long the lock was waited for.
+    timeout: Duration,
+  },
+  /// A hook set in the options returned an error.
+  #[error("`{hook}` hook failed: {source}")]
+  HookFailed {
+    /// The hook that failed, e.g. `pre_deploy`.
+    hook: &'static str,
+    /// The error the hook returned.
+    source: HookError,
+  },
+}
+
+/// Captured stderr as a suffix to a failure message, if there is any.
+fn stderr_suffix(stderr: &str) -> String {
+  if stderr.is_empty() {
+    String::new()
+  } else {
+    format!(":\n{}", stderr)
+  }
+}
+
+/// Describe what was being done when a work directory operation failed,
+/// turning it into an [`Error::WorkspacePrepFailed`].
+pub(crate) trait WorkspaceContext<T> {
+  fn context(self, what: &str) -> Result<T>;
+  fn with_context(self, what: impl FnOnce() -> String) -> Result<T>;
+}
+
+impl<T> WorkspaceContext<T> for io::Result<T> {
+  fn context(self, what: &str) -> Result<T> {
+    self.with_context(|| what.to_string())
+  }
+
+  fn with_context(self, what: impl FnOnce() -> String) -> Result<T> {
+    self.map_err(|err| {
+      Error::WorkspacePrepFailed(io::Error::new(
+        err.kind(),
+        format!("{}: {}", what(), err),
+      ))
+    })
+  }
+}
+
+impl<T> WorkspaceContext<T> for Option<T> {
+  fn context(self, what: &str) -> Result<T> {
+    self.with_context(|| what.to_string())
+  }
+
+  fn with_context(self, what: impl FnOnce() -> String) -> Result<T> {
+    self.ok_or_else(|| {
+      Error::WorkspacePrepFailed(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        what(),
+      ))
+    })
+  }
+}
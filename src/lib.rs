@@ -1,61 +1,1073 @@
 //! Library API for Terraform ephemeral deployments.
 //!
-//! Exposes two functions:
+//! Exposes the following functions:
 //! - `deploy`: applies a Terraform configuration and returns its outputs
+//! - `deploy_typed`: like `deploy`, but converts outputs to `OutputValue`
+//! - `deploy_detailed`: like `deploy`, but also reports output metadata
+//! - `get_outputs`: reads the outputs of an existing deployment
+//! - `status`: tells whether a configuration is initialized, deployed or
+//!   destroyed
 //! - `undeploy`: destroys an existing Terraform configuration
+//! - `refresh`: updates the state to match real infrastructure, changing
+//!   nothing
+//! - `plan`: previews the changes `deploy` would make and saves the plan
+//! - `plan_to_file`: like `plan`, but saves the plan to a given path
+//! - `deploy_from_plan`: applies a plan previously saved by `plan`
+//! - `deploy_from_files`, `undeploy_from_files`: deploy or destroy a
+//!   configuration held in memory
+//! - `parse_plan_json`: turns Terraform's JSON plan into a `ChangeSet`
+//! - `drift_detect`: reports resources changed outside of Terraform
+//! - `validate`: checks a configuration without touching any state
+//! - `import_resource`: brings an existing resource under management
+//! - `state_list`, `state_show`: inspect the resources in the state
+//! - `state_move`, `state_remove`: rename resources in the state or forget
+//!   them, without touching the real infrastructure
+//! - `force_unlock`: releases a state lock left behind by a dead process
+//! - `clean`: removes the temporary work directory of a configuration
+//! - `clean_workspaces`: removes every work directory left unused for a
+//!   given time
+//! - `list_workspaces`: describes every work directory atar has created
+//! - `workspace_new`, `workspace_select`, `workspace_list`,
+//!   `workspace_delete`: manage Terraform workspaces in a work directory
+//!
+//! `deploy_with_options` and `undeploy_with_options` accept a
+//! [`DeployOptions`] builder for everything beyond variables and logging.
+//!
+//! Functions taking the path of a Terraform file also accept the directory
+//! holding it.
+//!
+//! Every function returns [`Error`] on failure, so callers can match on
+//! what went wrong.
+//!
+//! Progress messages are emitted as `tracing` events; install a subscriber
+//! to see them. Each step runs in an `info` span (`prepare_workspace`,
+//! `terraform_init`, `terraform_apply`, `terraform_output` and
+//! `terraform_destroy`) recording the work directory, binary, parallelism
+//! and workspace.
+//!
+//! With the `async` feature (or its alias `tokio`) enabled, `deploy_async`
+//! and `undeploy_async` provide the same operations on top of
+//! `tokio::process::Command`, awaiting Terraform instead of blocking a
+//! thread. The synchronous API is always available.
+
+mod config;
+mod env_file;
+mod error;
+mod options;
+mod output;
+mod plan;
+mod process;
+mod retry;
+mod state;
+mod validate;
+mod workspace;
+
+pub use config::Config;
+pub use error::{AtarError, Error, HookError};
+pub use options::{
+  DeployOptions, Engine, LogConfig, LogLevel, ProgressEvent, TfLogLevel,
+};
+pub use output::{OutputValue, TerraformOutput, TypedOutput};
+pub use plan::{
+  parse_plan_json, Action, ChangeCounts, ChangeSet, DriftReport, PlanSummary,
+  ResourceChange,
+};
+pub use retry::{BackoffStrategy, RetryPolicy};
+pub use state::DeploymentStatus;
+pub use validate::{Diagnostic, DiagnosticRange, ValidationError};
+pub use workspace::WorkspaceInfo;
 
-use anyhow::{Context, Result, bail};
+use error::{Result, WorkspaceContext};
+use options::{Callback, WorkDirHookFn};
+#[cfg(feature = "async")]
+use process::run_command_async;
+use process::{
+  command_failed, run_command, run_command_capture, step_deadline, LineHandler,
+  LineHandlers,
+};
 use serde_json::{self, Value};
 use std::{
   collections::HashMap,
-  env,
-  fs,
+  fs, io, mem,
   path::{Path, PathBuf},
   process::{Command, Stdio},
+  sync::{Arc, Mutex},
+  thread,
+  time::{Duration, Instant},
 };
-use sha2::{Digest, Sha256};
+#[cfg(feature = "async")]
+use tracing::Instrument;
+use workspace::{VarsFile, WorkspaceLock};
+
+/// Name of the plan file saved by `plan` inside the work directory.
+const PLAN_FILE: &str = "plan.tfplan";
+
+/// File written inside the Terraform data directory once a full
+/// `terraform init` succeeded there. Validation initializes with
+/// `-backend=false`, which creates the data directory without it.
+const INIT_MARKER: &str = "atar-initialized";
 
-fn ensure_terraform_installed() -> Result<()> {
-  let status = Command::new("terraform")
+/// Check whether `bin -version` runs successfully.
+fn binary_works(bin: &Path) -> bool {
+  Command::new(bin)
     .arg("-version")
     .stdout(Stdio::null())
     .stderr(Stdio::null())
     .status()
-    .context("Failed to execute `terraform -version`")?;
-  if !status.success() {
-    bail!("Terraform must be installed and in PATH");
+    .is_ok_and(|status| status.success())
+}
+
+/// Version reported by `bin -version`, whose first line reads e.g.
+/// `Terraform v1.9.0` or `OpenTofu v1.8.2`.
+fn terraform_version(bin: &Path) -> Result<semver::Version> {
+  let command = format!("{} -version", bin.display());
+  let output = Command::new(bin)
+    .arg("-version")
+    .output()
+    .map_err(|source| Error::CommandIo {
+      command: command.clone(),
+      source,
+    })?;
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  stdout
+    .lines()
+    .next()
+    .and_then(|line| {
+      line
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix('v'))
+    })
+    .and_then(|version| semver::Version::parse(version).ok())
+    .ok_or_else(|| Error::CommandIo {
+      command,
+      source: io::Error::other(format!(
+        "unrecognized version output: {}",
+        stdout.trim()
+      )),
+    })
+}
+
+/// Fail if the binary chosen in `options` is older than its
+/// `min_terraform_version`.
+fn check_terraform_version(options: &DeployOptions) -> Result<()> {
+  let Some(required) = &options.min_terraform_version else {
+    return Ok(());
+  };
+  let found = terraform_version(options.program())?;
+  tracing::debug!("Found Terraform {}", found);
+  if found < *required {
+    return Err(Error::TerraformVersionTooOld {
+      required: required.clone(),
+      found,
+    });
+  }
+  Ok(())
+}
+
+/// Pick the Terraform binary to run and check that it is installed.
+///
+/// An explicit binary wins over an explicit engine; with neither set,
+/// `terraform` is preferred and `tofu` is used as a fallback. Returns a copy
+/// of `options` with the chosen binary filled in, after rejecting invalid
+/// settings and binaries older than `min_terraform_version`. Env files are
+/// read here, so they are current for every operation.
+fn ensure_tf_binary_installed(
+  options: &DeployOptions,
+) -> Result<DeployOptions> {
+  let mut resolved = resolve_tf_binary(options)?;
+  check_terraform_version(&resolved)?;
+  resolved.env_file_vars = env_file::load(&options.env_files)?;
+  Ok(resolved)
+}
+
+/// The part of [`ensure_tf_binary_installed`] that picks the binary.
+fn resolve_tf_binary(options: &DeployOptions) -> Result<DeployOptions> {
+  options.check()?;
+  let bin = match (&options.terraform_bin, options.engine) {
+    (Some(bin), _) => bin.clone(),
+    (None, Some(engine)) => PathBuf::from(engine.binary_name()),
+    (None, None) => {
+      let detected = [Engine::Terraform, Engine::OpenTofu]
+        .into_iter()
+        .find(|engine| binary_works(Path::new(engine.binary_name())));
+      match detected {
+        Some(engine) => {
+          return Ok(options.clone().engine(engine).terraform_bin(
+            engine.binary_name(),
+          ));
+        }
+        None => {
+          return Err(Error::TerraformNotInstalled {
+            binary: "terraform or tofu".to_string(),
+          })
+        }
+      }
+    }
+  };
+  if !binary_works(&bin) {
+    return Err(Error::TerraformNotInstalled {
+      binary: bin.display().to_string(),
+    });
+  }
+  Ok(options.clone().terraform_bin(bin))
+}
+
+/// Resolve the directory holding the Terraform file at `file`.
+///
+/// `file` may also be the directory itself, as long as it holds at least
+/// one `.tf` or `.tf.json` file.
+fn source_dir(file: &Path) -> Result<PathBuf> {
+  let file = file
+    .canonicalize()
+    .context("Failed to canonicalize Terraform path")?;
+  if file.is_dir() {
+    let entries = fs::read_dir(&file)
+      .with_context(|| format!("Failed to read directory {:?}", file))?;
+    let has_tf = entries.filter_map(|entry| entry.ok()).any(|entry| {
+      let name = entry.file_name();
+      let name = name.to_string_lossy();
+      name.ends_with(".tf") || name.ends_with(".tf.json")
+    });
+    if !has_tf {
+      return Err(Error::NoTerraformFiles { dir: file });
+    }
+    return Ok(file);
+  }
+  let src_dir = file
+    .parent()
+    .context("Cannot determine Terraform directory")?;
+  Ok(src_dir.to_path_buf())
+}
+
+/// Resolve the Terraform file at `file` and prepare its work directory,
+/// which stays locked until the returned guard is dropped.
+fn resolve_work_dir(
+  file: &Path,
+  options: &DeployOptions,
+) -> Result<(PathBuf, WorkspaceLock)> {
+  let src_dir = source_dir(file)?;
+  create_plugin_cache_dir(options)?;
+  let span = tracing::info_span!(
+    "prepare_workspace",
+    source_dir = %src_dir.display(),
+    work_dir = tracing::field::Empty,
+  );
+  let (work_dir, lock) = span.in_scope(|| {
+    workspace::prepare_work_dir(
+      &src_dir,
+      &options.resolved_work_root(),
+      &options.ignore_patterns,
+      &options.log,
+      options.lock_timeout,
+    )
+  })?;
+  span.record("work_dir", tracing::field::display(work_dir.display()));
+  Ok((work_dir, lock))
+}
+
+/// Run `work` with a copy of `options` on a blocking thread, within the
+/// current span, so the runtime's workers stay free.
+#[cfg(feature = "async")]
+async fn run_blocking<T, F>(options: &DeployOptions, work: F) -> Result<T>
+where
+  T: Send + 'static,
+  F: FnOnce(&DeployOptions) -> Result<T> + Send + 'static,
+{
+  let options = options.clone();
+  let span = tracing::Span::current();
+  let work = move || span.in_scope(|| work(&options));
+  match tokio::task::spawn_blocking(work).await {
+    Ok(done) => done,
+    Err(err) => std::panic::resume_unwind(err.into_panic()),
+  }
+}
+
+/// Async counterpart of [`resolve_work_dir`], copying and waiting for the
+/// lock on a blocking thread.
+#[cfg(feature = "async")]
+async fn resolve_work_dir_async(
+  file: &Path,
+  options: &DeployOptions,
+) -> Result<(PathBuf, WorkspaceLock)> {
+  let file = file.to_path_buf();
+  run_blocking(options, move |options| resolve_work_dir(&file, options)).await
+}
+
+/// Create the plugin cache directory of `options`, if one is used.
+fn create_plugin_cache_dir(options: &DeployOptions) -> Result<()> {
+  if let Some(dir) = options.resolved_plugin_cache_dir() {
+    // Terraform ignores a cache directory that does not exist.
+    std::fs::create_dir_all(&dir).with_context(|| {
+      format!("Failed to create plugin cache directory {:?}", dir)
+    })?;
   }
   Ok(())
 }
 
-/// Recursively copy a directory tree from `src` to `dst`.
-fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
-  fs::create_dir_all(dst).with_context(|| format!("Failed to create directory {:?}", dst))?;
-  for entry in fs::read_dir(src).with_context(|| format!("Failed to read directory {:?}", src))? {
-    let entry = entry.with_context(|| format!("Failed to access entry in {:?}", src))?;
-    let path = entry.path();
-    let dest = dst.join(entry.file_name());
-    if path.is_dir() {
-      copy_dir_recursive(&path, &dest)?;
-    } else {
-      fs::copy(&path, &dest)
-        .with_context(|| format!("Failed to copy file {:?} to {:?}", path, dest))?;
+/// Span around the Terraform operation `name` run in `work_dir`, carrying
+/// the binary, parallelism and workspace from `options`.
+macro_rules! operation_span {
+  ($name:literal, $work_dir:expr, $options:expr) => {
+    tracing::info_span!(
+      $name,
+      work_dir = %$work_dir.display(),
+      binary = $options
+        .terraform_bin
+        .as_deref()
+        .map(|bin| tracing::field::display(bin.display())),
+      parallelism = $options.parallelism,
+      workspace_name = $options.workspace.as_deref(),
+    )
+  };
+}
+
+/// Terraform step whose output is shown or silenced as one, see
+/// [`LogConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+  Init,
+  Apply,
+  Destroy,
+}
+
+impl Step {
+  /// Whether `log` shows Terraform's output for this step.
+  fn shown(self, log: &LogConfig) -> bool {
+    match self {
+      Step::Init => log.show_init,
+      Step::Apply => log.show_apply,
+      Step::Destroy => log.show_destroy,
+    }
+  }
+}
+
+/// Build a Terraform command running in `work_dir`, silenced unless
+/// `step` is shown.
+fn terraform_command(
+  work_dir: &Path,
+  options: &DeployOptions,
+  step: Step,
+) -> Command {
+  let mut cmd = options.command(work_dir);
+  if !step.shown(&options.log) {
+    cmd.stdout(Stdio::null()).stderr(Stdio::null());
+  }
+  cmd
+}
+
+/// Append the variable and var-file flags shared by apply, plan, destroy
+/// and import.
+///
+/// Var-files are copied into `work_dir` first, so that relative paths in
+/// their values resolve against the copied sources, and come before `-var`
+/// flags so that those override them.
+fn push_var_args(
+  cmd: &mut Command,
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<()> {
+  for file in workspace::copy_var_files(work_dir, &options.var_files)? {
+    cmd.arg(format!("-var-file={}", file.display()));
+  }
+  if options.vars_via_file && !options.vars.is_empty() {
+    let file = workspace::vars_file_path();
+    cmd.arg(format!("-var-file={}", file.display()));
+  } else {
+    for (k, v) in &options.vars {
+      cmd.arg("-var").arg(format!("{}={}", k, v));
     }
   }
   Ok(())
 }
 
-/// Prepare a deterministic temp workspace based on the source directory path.
-fn prepare_work_dir(src_dir: &Path) -> Result<PathBuf> {
-  let mut hasher = Sha256::new();
-  hasher.update(src_dir.to_string_lossy().as_bytes());
-  let hash = format!("{:x}", hasher.finalize());
-  let work = env::temp_dir().join("atar").join(hash);
-  if !work.exists() {
-    println!("Copying Terraform files to temporary directory {}", work.display());
-    copy_dir_recursive(src_dir, &work)?;
+/// Write the variables file read by [`push_var_args`], if variables are
+/// passed that way. It is removed when the returned guard is dropped.
+fn write_vars_file(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<Option<VarsFile>> {
+  if !options.vars_via_file || options.vars.is_empty() {
+    return Ok(None);
   }
-  Ok(work)
+  workspace::write_vars_file(work_dir, &options.vars).map(Some)
+}
+
+/// Append the target, parallelism and state lock flags shared by apply,
+/// plan and destroy.
+fn push_scope_args(cmd: &mut Command, options: &DeployOptions) {
+  for target in &options.targets {
+    cmd.arg(format!("-target={}", target));
+  }
+  if let Some(parallelism) = options.parallelism {
+    cmd.arg(format!("-parallelism={}", parallelism));
+  }
+  if let Some(lock) = options.state_lock {
+    cmd.arg(format!("-lock={}", lock));
+  }
+}
+
+/// Append a `-replace` flag per resource to recreate, for apply and plan.
+fn push_replace_args(cmd: &mut Command, options: &DeployOptions) {
+  for resource in &options.replace_resources {
+    cmd.arg(format!("-replace={}", resource));
+  }
+}
+
+/// Build `terraform workspace select -or-create` for the configured
+/// workspace, if any.
+fn workspace_command(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Option<Command> {
+  let workspace = options.workspace.as_ref()?;
+  let mut cmd = terraform_command(work_dir, options, Step::Init);
+  cmd
+    .arg("workspace")
+    .arg("select")
+    .arg("-or-create")
+    .arg(workspace);
+  Some(cmd)
+}
+
+/// Switch to the configured Terraform workspace, if any.
+fn select_workspace(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<()> {
+  match workspace_command(work_dir, options) {
+    Some(cmd) => run_command(
+      cmd,
+      "terraform workspace select",
+      deadline,
+      line_handlers(options, Step::Init),
+    ),
+    None => Ok(()),
+  }
+}
+
+/// Async counterpart of [`select_workspace`].
+#[cfg(feature = "async")]
+async fn select_workspace_async(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<()> {
+  match workspace_command(work_dir, options) {
+    Some(cmd) => {
+      run_command_async(
+        cmd,
+        "terraform workspace select",
+        deadline,
+        line_handlers(options, Step::Init),
+      )
+      .await
+    }
+    None => Ok(()),
+  }
+}
+
+fn init_command(work_dir: &Path, options: &DeployOptions) -> Command {
+  let mut cmd = terraform_command(work_dir, options, Step::Init);
+  cmd.arg("init");
+  for file in &options.backend_config_files {
+    cmd.arg(format!("-backend-config={}", file.display()));
+  }
+  for (key, value) in &options.backend_config {
+    cmd.arg(format!("-backend-config={}={}", key, value));
+  }
+  if options.init_upgrade {
+    cmd.arg("-upgrade");
+  }
+  if options.init_reconfigure {
+    cmd.arg("-reconfigure");
+  }
+  if options.init_migrate_state {
+    cmd.arg("-migrate-state");
+  }
+  cmd
+}
+
+/// Run `terraform init` unless `work_dir` has been initialized with its
+/// backend already and no upgrade was asked for.
+fn init_if_needed(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<()> {
+  let marker = options.resolved_data_dir(work_dir).join(INIT_MARKER);
+  if marker.exists() && !options.init_upgrade {
+    return Ok(());
+  }
+  run_init(work_dir, options, deadline)
+}
+
+/// Record that `terraform init` succeeded in `work_dir`, see
+/// [`INIT_MARKER`].
+fn mark_initialized(work_dir: &Path, options: &DeployOptions) -> Result<()> {
+  // Terraform leaves the data directory out for configs with nothing to
+  // install.
+  let data_dir = options.resolved_data_dir(work_dir);
+  std::fs::create_dir_all(&data_dir).with_context(|| {
+    format!("Failed to create data directory {:?}", data_dir)
+  })?;
+  let marker = data_dir.join(INIT_MARKER);
+  std::fs::write(&marker, "")
+    .with_context(|| format!("Failed to write {:?}", marker))
+}
+
+/// Run `terraform init` in `work_dir`.
+fn run_init(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<()> {
+  options.log.info("Initializing Terraform...");
+  operation_span!("terraform_init", work_dir, options).in_scope(|| {
+    run_command(
+      init_command(work_dir, options),
+      "terraform init",
+      step_deadline(deadline, options.init_timeout),
+      line_handlers(options, Step::Init),
+    )
+  })?;
+  mark_initialized(work_dir, options)
+}
+
+/// Async counterpart of [`run_init`].
+#[cfg(feature = "async")]
+async fn run_init_async(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<()> {
+  options.log.info("Initializing Terraform...");
+  run_command_async(
+    init_command(work_dir, options),
+    "terraform init",
+    step_deadline(deadline, options.init_timeout),
+    line_handlers(options, Step::Init),
+  )
+  .instrument(operation_span!("terraform_init", work_dir, options))
+  .await?;
+  let work_dir = work_dir.to_path_buf();
+  run_blocking(options, move |options| mark_initialized(&work_dir, options))
+    .await
+}
+
+fn apply_command(work_dir: &Path, options: &DeployOptions) -> Result<Command> {
+  let mut cmd = terraform_command(work_dir, options, Step::Apply);
+  cmd.arg("apply").arg("-auto-approve").arg("-json");
+  if options.refresh_only {
+    cmd.arg("-refresh-only");
+  }
+  push_var_args(&mut cmd, work_dir, options)?;
+  push_scope_args(&mut cmd, options);
+  push_replace_args(&mut cmd, options);
+  cmd.args(&options.extra_args);
+  Ok(cmd)
+}
+
+fn output_command(work_dir: &Path, options: &DeployOptions) -> Command {
+  let mut cmd = options.command(work_dir);
+  cmd.arg("output").arg("-json");
+  if let Some(workspace) = &options.workspace {
+    cmd.env("TF_WORKSPACE", workspace);
+  }
+  cmd
+}
+
+fn refresh_command(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<Command> {
+  let mut cmd = terraform_command(work_dir, options, Step::Apply);
+  cmd.arg("apply").arg("-refresh-only").arg("-auto-approve");
+  push_var_args(&mut cmd, work_dir, options)?;
+  push_scope_args(&mut cmd, options);
+  Ok(cmd)
+}
+
+fn plan_command(
+  work_dir: &Path,
+  plan_file: &Path,
+  options: &DeployOptions,
+) -> Result<Command> {
+  let mut cmd = terraform_command(work_dir, options, Step::Apply);
+  cmd
+    .arg("plan")
+    .arg("-json")
+    .arg(format!("-out={}", plan_file.display()));
+  push_var_args(&mut cmd, work_dir, options)?;
+  push_scope_args(&mut cmd, options);
+  push_replace_args(&mut cmd, options);
+  Ok(cmd)
+}
+
+fn drift_command(work_dir: &Path, options: &DeployOptions) -> Result<Command> {
+  let mut cmd = terraform_command(work_dir, options, Step::Apply);
+  cmd.arg("plan").arg("-json").arg("-refresh=true");
+  push_var_args(&mut cmd, work_dir, options)?;
+  push_scope_args(&mut cmd, options);
+  Ok(cmd)
+}
+
+fn destroy_command(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<Command> {
+  let mut cmd = terraform_command(work_dir, options, Step::Destroy);
+  cmd.arg("destroy").arg("-auto-approve");
+  push_var_args(&mut cmd, work_dir, options)?;
+  push_scope_args(&mut cmd, options);
+  cmd.args(&options.extra_args);
+  Ok(cmd)
+}
+
+/// Route Terraform output to the output sink, if any.
+///
+/// Stderr is always captured for error messages, so when `step` is shown
+/// without a sink it is echoed line by line. For apply, each stdout line is
+/// also reported as [`ProgressEvent::ApplyLine`] whenever it can be
+/// observed, i.e. when apply output is shown or with an output sink.
+fn line_handlers(options: &DeployOptions, step: Step) -> LineHandlers {
+  let show = step.shown(&options.log);
+  let sink = options.output_sink.clone();
+  let progress = options.progress.clone().filter(|_| step == Step::Apply);
+  let stderr: Option<LineHandler> = match &sink {
+    Some(sink) => Some(sink.0.clone()),
+    None if show => Some(Arc::new(|line: &str| eprintln!("{}", line))),
+    None => None,
+  };
+  if progress.is_none() || !(show || sink.is_some()) {
+    return LineHandlers {
+      stdout: sink.map(|sink| sink.0 as LineHandler),
+      stderr,
+    };
+  }
+  let stdout: LineHandler = Arc::new(move |line: &str| {
+    match &sink {
+      Some(sink) => (sink.0)(line),
+      None => println!("{}", line),
+    }
+    if let Some(progress) = &progress {
+      (progress.0)(ProgressEvent::ApplyLine(line.to_string()));
+    }
+  });
+  LineHandlers {
+    stdout: Some(stdout),
+    stderr,
+  }
+}
+
+/// What `terraform apply -json` reported besides the lines it showed.
+#[derive(Default)]
+struct ApplyReport {
+  /// Counts from the `change_summary` message, if there was one.
+  counts: Option<ChangeCounts>,
+  /// Error diagnostics, which `-json` writes to stdout instead of stderr.
+  errors: String,
+}
+
+/// Handlers for `terraform apply -json`.
+///
+/// Each message is shown as the line Terraform would have printed without
+/// `-json`, while the change summary and error diagnostics are collected
+/// into `report`.
+fn apply_line_handlers(
+  options: &DeployOptions,
+  report: &Arc<Mutex<ApplyReport>>,
+) -> LineHandlers {
+  let mut handlers = line_handlers(options, Step::Apply);
+  let show = handlers.stdout.take().or_else(|| {
+    Step::Apply
+      .shown(&options.log)
+      .then(|| Arc::new(|line: &str| println!("{}", line)) as LineHandler)
+  });
+  let report = report.clone();
+  handlers.stdout = Some(Arc::new(move |line: &str| {
+    let text = match serde_json::from_str::<Value>(line) {
+      Ok(message) => {
+        let mut report = report.lock().unwrap_or_else(|e| e.into_inner());
+        record_apply_message(&mut report, &message);
+        message
+          .get("@message")
+          .and_then(Value::as_str)
+          .unwrap_or(line)
+          .to_string()
+      }
+      Err(_) => line.to_string(),
+    };
+    if let Some(show) = &show {
+      show(&text);
+    }
+  }));
+  handlers
+}
+
+/// Add the change summary or error diagnostic in `message`, if any, to
+/// `report`.
+fn record_apply_message(report: &mut ApplyReport, message: &Value) {
+  if let Some(counts) = plan::change_counts(message) {
+    report.counts = Some(counts);
+  }
+  if message.get("@level").and_then(Value::as_str) != Some("error") {
+    return;
+  }
+  if let Some(text) = message.get("@message").and_then(Value::as_str) {
+    report.errors.push_str(text);
+    report.errors.push('\n');
+  }
+  if let Some(detail) = message["diagnostic"]["detail"].as_str() {
+    report.errors.push_str(detail);
+    report.errors.push('\n');
+  }
+}
+
+/// Turn the result of a `terraform apply -json` run into its change counts,
+/// adding the error diagnostics in `report` to a failure.
+fn apply_outcome(
+  result: Result<()>,
+  report: &Mutex<ApplyReport>,
+) -> Result<Option<ChangeCounts>> {
+  let report =
+    mem::take(&mut *report.lock().unwrap_or_else(|e| e.into_inner()));
+  match result {
+    Ok(()) => Ok(report.counts),
+    Err(Error::ApplyFailed { stderr, exit_code }) => {
+      let stderr = match (report.errors.trim_end(), stderr.as_str()) {
+        ("", stderr) => stderr.to_string(),
+        (errors, "") => errors.to_string(),
+        (errors, stderr) => format!("{}\n{}", errors, stderr),
+      };
+      Err(Error::ApplyFailed { stderr, exit_code })
+    }
+    Err(err) => Err(err),
+  }
+}
+
+/// Run the `terraform apply -json` command `cmd`, returning the change
+/// counts it reported.
+fn run_apply(
+  cmd: Command,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<Option<ChangeCounts>> {
+  let report = Arc::default();
+  let result = run_command(
+    cmd,
+    "terraform apply",
+    step_deadline(deadline, options.apply_timeout),
+    apply_line_handlers(options, &report),
+  );
+  apply_outcome(result, &report)
+}
+
+/// Async counterpart of [`run_apply`].
+#[cfg(feature = "async")]
+async fn run_apply_async(
+  cmd: Command,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<Option<ChangeCounts>> {
+  let report = Arc::default();
+  let result = run_command_async(
+    cmd,
+    "terraform apply",
+    step_deadline(deadline, options.apply_timeout),
+    apply_line_handlers(options, &report),
+  )
+  .await;
+  apply_outcome(result, &report)
+}
+
+/// Decide whether the failed attempt `attempt` of `step` should be retried
+/// under `policy`, logging the retry if so.
+fn retry_delay(
+  step: &str,
+  policy: &RetryPolicy,
+  matcher: &regex::RegexSet,
+  attempt: u32,
+  err: &Error,
+) -> Option<Duration> {
+  let stderr = match err {
+    Error::ApplyFailed { stderr, .. } => stderr,
+    Error::DestroyFailed { stderr, .. } => stderr,
+    _ => return None,
+  };
+  let delay = policy.next_delay(matcher, attempt, stderr)?;
+  tracing::warn!(
+    "{} attempt {} of {} failed, retrying in {:?}",
+    step,
+    attempt,
+    policy.max_attempts,
+    delay
+  );
+  Some(delay)
+}
+
+/// Run `run` until it succeeds or fails in a way `policy` does not retry.
+fn with_retry<T>(
+  step: &str,
+  policy: &RetryPolicy,
+  mut run: impl FnMut() -> Result<T>,
+) -> Result<T> {
+  if !policy.enabled() {
+    return run();
+  }
+  let matcher = policy.matcher()?;
+  let mut attempt = 1;
+  loop {
+    let err = match run() {
+      Ok(value) => return Ok(value),
+      Err(err) => err,
+    };
+    match retry_delay(step, policy, &matcher, attempt, &err) {
+      Some(delay) => thread::sleep(delay),
+      None => return Err(err),
+    }
+    attempt += 1;
+  }
+}
+
+/// Async counterpart of [`with_retry`].
+#[cfg(feature = "async")]
+async fn with_retry_async<T, F>(
+  step: &str,
+  policy: &RetryPolicy,
+  mut run: impl FnMut() -> F,
+) -> Result<T>
+where
+  F: std::future::Future<Output = Result<T>>,
+{
+  if !policy.enabled() {
+    return run().await;
+  }
+  let matcher = policy.matcher()?;
+  let mut attempt = 1;
+  loop {
+    let err = match run().await {
+      Ok(value) => return Ok(value),
+      Err(err) => err,
+    };
+    match retry_delay(step, policy, &matcher, attempt, &err) {
+      Some(delay) => tokio::time::sleep(delay).await,
+      None => return Err(err),
+    }
+    attempt += 1;
+  }
+}
+
+/// Run `terraform apply`, retrying transient failures as configured by
+/// [`DeployOptions::retry`], and return the change counts it reported.
+fn apply_with_retry(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<Option<ChangeCounts>> {
+  let _span = operation_span!("terraform_apply", work_dir, options).entered();
+  with_retry("Apply", &options.retry, || {
+    run_apply(apply_command(work_dir, options)?, options, deadline)
+  })
+}
+
+/// Async counterpart of [`apply_with_retry`].
+#[cfg(feature = "async")]
+async fn apply_with_retry_async(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<Option<ChangeCounts>> {
+  with_retry_async("Apply", &options.retry, || async {
+    run_apply_async(apply_command(work_dir, options)?, options, deadline).await
+  })
+  .await
+}
+
+/// Run `terraform destroy`, retrying transient failures as configured by
+/// [`DeployOptions::destroy_retry`].
+fn destroy_with_retry(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<()> {
+  let _span = operation_span!("terraform_destroy", work_dir, options).entered();
+  with_retry("Destroy", &options.destroy_retry, || {
+    run_command(
+      destroy_command(work_dir, options)?,
+      "terraform destroy",
+      step_deadline(deadline, options.destroy_timeout),
+      line_handlers(options, Step::Destroy),
+    )
+  })
+}
+
+/// Async counterpart of [`destroy_with_retry`].
+#[cfg(feature = "async")]
+async fn destroy_with_retry_async(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<()> {
+  with_retry_async("Destroy", &options.destroy_retry, || async {
+    run_command_async(
+      destroy_command(work_dir, options)?,
+      "terraform destroy",
+      step_deadline(deadline, options.destroy_timeout),
+      line_handlers(options, Step::Destroy),
+    )
+    .await
+  })
+  .await
+}
+
+/// How long each step of a deploy took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StepDurations {
+  /// `terraform init`, zero when it was not run.
+  pub init: Duration,
+  /// `terraform apply`, retries and backoff delays included.
+  pub apply: Duration,
+  /// Reading the outputs.
+  pub output: Duration,
+  /// The whole operation, preparing the work directory included.
+  pub total: Duration,
+}
+
+/// Outcome of a successful deploy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeployResult {
+  /// Every Terraform output, keyed by name.
+  pub outputs: HashMap<String, TerraformOutput>,
+  /// Temporary work directory Terraform ran in, holding its state, saved
+  /// plans and `.terraform` provider cache.
+  pub work_dir: PathBuf,
+  /// How long each step took.
+  pub durations: StepDurations,
+  /// Whether the deploy changed any resource. `true` when Terraform did
+  /// not report its changes, so callers never skip work by mistake.
+  pub changed: bool,
+  /// Resources added, changed and destroyed, as reported by Terraform;
+  /// all zero when it reported nothing.
+  pub changes: ChangeCounts,
+}
+
+impl DeployResult {
+  /// Result with the change fields filled in from `counts`, as reported by
+  /// `terraform apply`.
+  fn new(
+    outputs: HashMap<String, TerraformOutput>,
+    work_dir: PathBuf,
+    durations: StepDurations,
+    counts: Option<ChangeCounts>,
+  ) -> DeployResult {
+    DeployResult {
+      outputs,
+      work_dir,
+      durations,
+      changed: counts.is_none_or(|counts| !counts.is_empty()),
+      changes: counts.unwrap_or_default(),
+    }
+  }
+}
+
+/// Log how long `terraform apply` took.
+fn log_apply_duration(options: &DeployOptions, elapsed: Duration) {
+  options.log.info(format_args!(
+    "Apply completed in {:.1}s",
+    elapsed.as_secs_f64()
+  ));
+}
+
+/// Read the outputs of the state in `work_dir`.
+fn read_outputs(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<HashMap<String, TerraformOutput>> {
+  let _span = operation_span!("terraform_output", work_dir, options).entered();
+  outputs_from(output_command(work_dir, options).output())
+}
+
+/// Async counterpart of [`read_outputs`].
+#[cfg(feature = "async")]
+async fn read_outputs_async(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<HashMap<String, TerraformOutput>> {
+  let output = tokio::process::Command::from(output_command(work_dir, options))
+    .output()
+    .instrument(operation_span!("terraform_output", work_dir, options))
+    .await;
+  outputs_from(output)
+}
+
+/// Parse the outputs from the result of running `terraform output -json`.
+fn outputs_from(
+  output: io::Result<std::process::Output>,
+) -> Result<HashMap<String, TerraformOutput>> {
+  let output = output.map_err(|source| Error::CommandIo {
+    command: "terraform output -json".to_string(),
+    source,
+  })?;
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    return Err(command_failed(
+      "terraform output -json",
+      output.status,
+      &stderr,
+    ));
+  }
+  output::parse_outputs(&output.stdout)
+}
+
+/// Run the work directory `hook`, if set, reporting a failure as
+/// [`Error::HookFailed`] for `name`.
+fn run_hook(
+  name: &'static str,
+  hook: Option<&Callback<WorkDirHookFn>>,
+  work_dir: &Path,
+) -> Result<()> {
+  match hook {
+    Some(hook) => (hook.0)(work_dir)
+      .map_err(|source| Error::HookFailed { hook: name, source }),
+    None => Ok(()),
+  }
+}
+
+/// Save `outputs` and hand them to the `post_deploy` hook, as configured
+/// by `options`.
+fn publish_outputs(
+  options: &DeployOptions,
+  outputs: &HashMap<String, TerraformOutput>,
+) -> Result<()> {
+  save_outputs(options, outputs)?;
+  run_post_deploy_hook(options, outputs)
+}
+
+/// Run the `post_deploy` hook of `options`, if set, with `outputs`.
+fn run_post_deploy_hook(
+  options: &DeployOptions,
+  outputs: &HashMap<String, TerraformOutput>,
+) -> Result<()> {
+  match &options.post_deploy {
+    Some(hook) => (hook.0)(&output::stringify_outputs(outputs.clone()))
+      .map_err(|source| Error::HookFailed {
+        hook: "post_deploy",
+        source,
+      }),
+    None => Ok(()),
+  }
+}
+
+/// Write `outputs` to the output file set in `options`, if any.
+fn save_outputs(
+  options: &DeployOptions,
+  outputs: &HashMap<String, TerraformOutput>,
+) -> Result<()> {
+  if let Some(path) = &options.output_file {
+    output::write_outputs_file(path, outputs)?;
+  }
+  Ok(())
 }
 
 /// Apply Terraform config at `file` with provided `vars`.
@@ -64,112 +1076,1210 @@ fn prepare_work_dir(src_dir: &Path) -> Result<PathBuf> {
 pub fn deploy<P: AsRef<Path>>(
   file: P,
   vars: &HashMap<String, String>,
-  debug: bool,
+  log: impl Into<LogConfig>,
 ) -> Result<HashMap<String, String>> {
-  ensure_terraform_installed()?;
-  let file = file
-    .as_ref()
-    .canonicalize()
-    .context("Failed to canonicalize Terraform path")?;
-  let src_dir = file
-    .parent()
-    .context("Cannot determine Terraform directory")?;
-  let work_dir = prepare_work_dir(src_dir)?;
+  deploy_detailed(file, vars, log).map(output::stringify_outputs)
+}
 
-  // init
-  println!("Initializing Terraform...");
+/// Apply Terraform config at `file` with provided `vars`.
+///
+/// Returns a map from output names to their values converted to
+/// [`OutputValue`], so lists and maps can be matched on directly. Use
+/// [`deploy_detailed`] to get the raw JSON instead.
+pub fn deploy_typed<P: AsRef<Path>>(
+  file: P,
+  vars: &HashMap<String, String>,
+  log: impl Into<LogConfig>,
+) -> Result<HashMap<String, TypedOutput>> {
+  deploy_detailed(file, vars, log).map(output::typed_outputs)
+}
 
-  let mut init = Command::new("terraform");
-  init.current_dir(&work_dir).arg("init");
-  if !debug {
-    init.stdout(Stdio::null()).stderr(Stdio::null());
+/// Apply Terraform config at `file` with provided `vars`.
+///
+/// Returns every output with its value, type and `sensitive` flag.
+pub fn deploy_detailed<P: AsRef<Path>>(
+  file: P,
+  vars: &HashMap<String, String>,
+  log: impl Into<LogConfig>,
+) -> Result<HashMap<String, TerraformOutput>> {
+  let options = DeployOptions::new().vars(vars.clone()).log(log.into());
+  deploy_with_options(file, &options).map(|result| result.outputs)
+}
+
+/// Apply Terraform config at `file` as configured by `options`.
+///
+/// Returns every output with its value, type and `sensitive` flag, together
+/// with the work directory Terraform ran in.
+pub fn deploy_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<DeployResult> {
+  let started = Instant::now();
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+  deploy_in(work_dir, options, started)
+}
+
+/// Deploy the Terraform config made of the in-memory `files`, keyed by
+/// their path relative to the module root, with `vars` on top of those set
+/// in `options`.
+///
+/// Saves consumers from writing `.tf` files to disk themselves. The files
+/// are written to a work directory named after a hash of their contents,
+/// so deploying the same files again reuses its state. Ignore patterns do
+/// not apply.
+pub fn deploy_from_files(
+  files: HashMap<PathBuf, Vec<u8>>,
+  vars: &HashMap<String, String>,
+  options: &DeployOptions,
+) -> Result<DeployResult> {
+  let started = Instant::now();
+  let options = &options.clone().vars(vars.clone());
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_files_work_dir(&files, options)?;
+  deploy_in(work_dir, options, started)
+}
+
+/// Destroy what [`deploy_from_files`] deployed from the same `files`, with
+/// `vars` on top of those set in `options`.
+///
+/// The work directory is found from the contents of `files`, and written
+/// again if it is gone.
+pub fn undeploy_from_files(
+  files: HashMap<PathBuf, Vec<u8>>,
+  vars: &HashMap<String, String>,
+  options: &DeployOptions,
+) -> Result<()> {
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &options.clone().vars(vars.clone());
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_files_work_dir(&files, options)?;
+  undeploy_in(&work_dir, options, deadline)
+}
+
+/// Prepare the work directory of the in-memory `files`, which stays locked
+/// until the returned guard is dropped.
+fn resolve_files_work_dir(
+  files: &HashMap<PathBuf, Vec<u8>>,
+  options: &DeployOptions,
+) -> Result<(PathBuf, WorkspaceLock)> {
+  create_plugin_cache_dir(options)?;
+  let span =
+    tracing::info_span!("prepare_workspace", work_dir = tracing::field::Empty);
+  let (work_dir, lock) = span.in_scope(|| {
+    workspace::prepare_files_work_dir(
+      files,
+      &options.resolved_work_root(),
+      &options.log,
+      options.lock_timeout,
+    )
+  })?;
+  span.record("work_dir", tracing::field::display(work_dir.display()));
+  Ok((work_dir, lock))
+}
+
+/// Run the `pre_deploy` hook in `work_dir` and write the vars file there.
+fn prepare_deploy(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<Option<VarsFile>> {
+  run_hook("pre_deploy", options.pre_deploy.as_ref(), work_dir)?;
+  write_vars_file(work_dir, options)
+}
+
+/// Deploy the prepared and locked `work_dir`, the deploy having begun at
+/// `started`.
+fn deploy_in(
+  work_dir: PathBuf,
+  options: &DeployOptions,
+  started: Instant,
+) -> Result<DeployResult> {
+  let deadline = options.timeout.map(|t| started + t);
+  let _vars_file = prepare_deploy(&work_dir, options)?;
+  let mut durations = StepDurations::default();
+
+  // init
+  options.emit(ProgressEvent::InitStarted);
+  let step = Instant::now();
+  run_init(&work_dir, options, deadline)?;
+  durations.init = step.elapsed();
+  options.emit(ProgressEvent::InitCompleted);
+  select_workspace(&work_dir, options, deadline)?;
+  if options.validate {
+    run_validate(&work_dir, options)?;
   }
-  let status = init
-    .status()
-    .context("Failed to execute `terraform init`")?;
-  if !status.success() {
-    bail!("`terraform init` failed with exit code {}", status);
+
+  options.log.info("Applying Terraform...");
+  options.emit(ProgressEvent::ApplyStarted);
+  let step = Instant::now();
+  let counts = apply_with_retry(&work_dir, options, deadline)?;
+  durations.apply = step.elapsed();
+  log_apply_duration(options, durations.apply);
+  options.emit(ProgressEvent::ApplyCompleted);
+
+  // output JSON
+  let step = Instant::now();
+  let outputs = read_outputs(&work_dir, options)?;
+  durations.output = step.elapsed();
+  publish_outputs(options, &outputs)?;
+  options.emit(ProgressEvent::OutputsReady);
+  durations.total = started.elapsed();
+  Ok(DeployResult::new(outputs, work_dir, durations, counts))
+}
+
+/// Refresh the state of Terraform config at `file` with provided `vars`,
+/// without changing any infrastructure.
+///
+/// Runs `terraform apply -refresh-only`, so the state is updated to match
+/// what actually exists. Returns the stringified outputs like [`deploy`],
+/// ready to be compared with those of the last deploy to spot drift.
+pub fn refresh<P: AsRef<Path>>(
+  file: P,
+  vars: &HashMap<String, String>,
+  log: impl Into<LogConfig>,
+) -> Result<HashMap<String, String>> {
+  let options = DeployOptions::new().vars(vars.clone()).log(log.into());
+  refresh_with_options(file, &options)
+    .map(|result| output::stringify_outputs(result.outputs))
+}
+
+/// Refresh the state of Terraform config at `file` as configured by
+/// `options`.
+pub fn refresh_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<DeployResult> {
+  let started = Instant::now();
+  let deadline = options.timeout.map(|t| started + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+  let _vars_file = write_vars_file(&work_dir, options)?;
+  let mut durations = StepDurations::default();
+
+  let step = Instant::now();
+  run_init(&work_dir, options, deadline)?;
+  durations.init = step.elapsed();
+  select_workspace(&work_dir, options, deadline)?;
+
+  options.log.info("Refreshing Terraform state...");
+  let step = Instant::now();
+  operation_span!("terraform_apply", &work_dir, options).in_scope(|| {
+    run_command(
+      refresh_command(&work_dir, options)?,
+      "terraform apply -refresh-only",
+      step_deadline(deadline, options.apply_timeout),
+      line_handlers(options, Step::Apply),
+    )
+  })?;
+  durations.apply = step.elapsed();
+
+  let step = Instant::now();
+  let outputs = read_outputs(&work_dir, options)?;
+  durations.output = step.elapsed();
+  durations.total = started.elapsed();
+  // A refresh only updates the state, never the infrastructure.
+  let counts = Some(ChangeCounts::default());
+  Ok(DeployResult::new(outputs, work_dir, durations, counts))
+}
+
+/// Read the outputs of Terraform config at `file` from its existing state,
+/// without running `init` or `apply`.
+///
+/// Returns the stringified outputs like [`deploy`]. Fails with
+/// [`Error::WorkDirMissing`] if the config has no work directory yet.
+pub fn get_outputs<P: AsRef<Path>>(
+  file: P,
+  log: impl Into<LogConfig>,
+) -> Result<HashMap<String, String>> {
+  get_outputs_with_options(file, &DeployOptions::new().log(log.into()))
+    .map(output::stringify_outputs)
+}
+
+/// Like [`get_outputs`], locating the work directory and running Terraform
+/// as configured by `options`, and returning every output in detail.
+pub fn get_outputs_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<HashMap<String, TerraformOutput>> {
+  let options = &ensure_tf_binary_installed(options)?;
+  let src_dir = source_dir(file.as_ref())?;
+  let work_dir =
+    workspace::work_dir_path(&src_dir, &options.resolved_work_root());
+  if !work_dir.is_dir() {
+    return Err(Error::WorkDirMissing { work_dir });
   }
+  let _lock = workspace::lock_work_dir(&work_dir, options.lock_timeout)?;
+  read_outputs(&work_dir, options)
+}
 
-  println!("Applying Terraform...");
-  {
-    let mut cmd = Command::new("terraform");
-    cmd.current_dir(&work_dir).arg("apply").arg("-auto-approve");
-    for (k, v) in vars {
-      cmd.arg("-var").arg(format!("{}={}", k, v));
+/// Plan Terraform config at `file` with provided `vars` without applying it.
+///
+/// The plan is saved as `plan.tfplan` in the work directory so it can be
+/// applied later with [`deploy_from_plan`].
+pub fn plan<P: AsRef<Path>>(
+  file: P,
+  vars: &HashMap<String, String>,
+  log: impl Into<LogConfig>,
+) -> Result<PlanSummary> {
+  let options = DeployOptions::new().vars(vars.clone()).log(log.into());
+  plan_with_options(file, &options)
+}
+
+/// Plan Terraform config at `file` as configured by `options`.
+pub fn plan_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<PlanSummary> {
+  plan_into(file.as_ref(), None, options)
+}
+
+/// Plan Terraform config at `file` with provided `vars`, saving the plan to
+/// `out_path` instead of the work directory.
+///
+/// Meant for two-phase deployments: store the plan as an artifact, then
+/// apply it unchanged with [`deploy_from_plan`] against the work directory
+/// reported in the returned summary. A relative `out_path` is resolved
+/// against the current directory.
+pub fn plan_to_file<P: AsRef<Path>>(
+  file: P,
+  vars: &HashMap<String, String>,
+  log: impl Into<LogConfig>,
+  out_path: &Path,
+) -> Result<PlanSummary> {
+  let options = DeployOptions::new().vars(vars.clone()).log(log.into());
+  plan_to_file_with_options(file, out_path, &options)
+}
+
+/// Like [`plan_to_file`], configured by `options`.
+pub fn plan_to_file_with_options<P: AsRef<Path>>(
+  file: P,
+  out_path: &Path,
+  options: &DeployOptions,
+) -> Result<PlanSummary> {
+  plan_into(file.as_ref(), Some(out_path), options)
+}
+
+/// Plan Terraform config at `file`, saving the plan to `out_path` or, by
+/// default, to [`PLAN_FILE`] in the work directory.
+fn plan_into(
+  file: &Path,
+  out_path: Option<&Path>,
+  options: &DeployOptions,
+) -> Result<PlanSummary> {
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file, options)?;
+  let _vars_file = write_vars_file(&work_dir, options)?;
+  let plan_file = match out_path {
+    Some(path) => {
+      std::path::absolute(path).context("Failed to resolve plan output path")?
     }
-    if !debug {
-      cmd.stdout(Stdio::null()).stderr(Stdio::null());
+    None => work_dir.join(PLAN_FILE),
+  };
+
+  run_init(&work_dir, options, deadline)?;
+
+  select_workspace(&work_dir, options, deadline)?;
+
+  options.log.info("Planning Terraform...");
+  let parsed = run_plan(
+    plan_command(&work_dir, &plan_file, options)?,
+    options,
+    deadline,
+  )?;
+  Ok(PlanSummary {
+    to_add: parsed.to_add,
+    to_change: parsed.to_change,
+    to_destroy: parsed.to_destroy,
+    raw_plan_json: Value::Array(parsed.messages),
+    work_dir,
+    plan_file,
+  })
+}
+
+/// Run the `terraform plan -json` command `cmd` and parse its output,
+/// echoing the plan's messages.
+fn run_plan(
+  cmd: Command,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<plan::ParsedPlan> {
+  let stdout = run_command_capture(
+    cmd,
+    "terraform plan",
+    deadline,
+    line_handlers(options, Step::Apply).stderr,
+  )?;
+  let parsed = plan::parse_plan_stream(&stdout)?;
+  echo_plan_messages(options, &parsed.messages);
+  Ok(parsed)
+}
+
+/// Show the `@message` of each of the plan's `messages` like apply output:
+/// through the output sink if one is set, or on stdout when shown.
+fn echo_plan_messages(options: &DeployOptions, messages: &[Value]) {
+  let echo = match line_handlers(options, Step::Apply).stdout {
+    Some(handler) => handler,
+    None if Step::Apply.shown(&options.log) => {
+      Arc::new(|line: &str| println!("{}", line)) as LineHandler
     }
-    let status = cmd
-      .status()
-      .context("Failed to execute `terraform apply`")?;
-    if !status.success() {
-      bail!("`terraform apply` failed with exit code {}", status);
+    None => return,
+  };
+  for message in messages {
+    if let Some(text) = message.get("@message").and_then(Value::as_str) {
+      echo(text);
     }
   }
+}
 
-  // output JSON
-  let output = Command::new("terraform")
-    .current_dir(&work_dir)
-    .arg("output")
-    .arg("-json")
+/// Check whether the resources of Terraform config at `file`, deployed with
+/// `vars`, were changed outside of Terraform.
+///
+/// Runs `terraform plan -refresh=true` without saving or applying the
+/// plan, so neither the infrastructure nor the state is modified. Meant for
+/// scheduled jobs that alert on unexpected changes.
+pub fn drift_detect<P: AsRef<Path>>(
+  file: P,
+  vars: &HashMap<String, String>,
+  log: impl Into<LogConfig>,
+) -> Result<DriftReport> {
+  let options = DeployOptions::new().vars(vars.clone()).log(log.into());
+  drift_detect_with_options(file, &options)
+}
+
+/// Like [`drift_detect`], running Terraform as configured by `options`.
+pub fn drift_detect_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<DriftReport> {
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+  let _vars_file = write_vars_file(&work_dir, options)?;
+
+  run_init(&work_dir, options, deadline)?;
+
+  select_workspace(&work_dir, options, deadline)?;
+
+  options.log.info("Checking for drift...");
+  let parsed = run_plan(drift_command(&work_dir, options)?, options, deadline)?;
+  Ok(plan::parse_drift_stream(&parsed.messages))
+}
+
+/// Apply the saved plan at `plan_path` inside `work_dir`.
+///
+/// The plan is already approved, so no `-auto-approve` is needed and no
+/// variables are passed. A relative `plan_path` is resolved against the
+/// current directory, like the `out_path` of [`plan_to_file`]. Returns the
+/// stringified outputs like [`deploy`].
+pub fn deploy_from_plan(
+  work_dir: &Path,
+  plan_path: &Path,
+  log: impl Into<LogConfig>,
+) -> Result<HashMap<String, String>> {
+  let options = DeployOptions::new().log(log.into());
+  deploy_from_plan_with_options(work_dir, plan_path, &options)
+    .map(|result| output::stringify_outputs(result.outputs))
+}
+
+/// Apply the saved plan at `plan_path` inside `work_dir` as configured by
+/// `options`.
+///
+/// Variables in `options` are ignored, as they are baked into the plan.
+pub fn deploy_from_plan_with_options(
+  work_dir: &Path,
+  plan_path: &Path,
+  options: &DeployOptions,
+) -> Result<DeployResult> {
+  let started = Instant::now();
+  let deadline = options.timeout.map(|t| started + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  if !workspace::is_work_dir(work_dir) {
+    return Err(Error::InvalidOptions(format!(
+      "{} is not an atar work directory",
+      work_dir.display()
+    )));
+  }
+  // Terraform runs inside the work directory
+  let plan_path = &std::path::absolute(plan_path)
+    .context("Failed to resolve saved plan path")?;
+  let _lock = workspace::lock_work_dir(work_dir, options.lock_timeout)?;
+  run_hook("pre_deploy", options.pre_deploy.as_ref(), work_dir)?;
+  let mut durations = StepDurations::default();
+
+  options.log.info(format_args!(
+    "Applying saved plan {}...",
+    plan_path.display()
+  ));
+  let mut cmd = terraform_command(work_dir, options, Step::Apply);
+  cmd.arg("apply").arg("-json").arg(plan_path);
+  options.emit(ProgressEvent::ApplyStarted);
+  let step = Instant::now();
+  let counts = operation_span!("terraform_apply", work_dir, options)
+    .in_scope(|| run_apply(cmd, options, deadline))?;
+  durations.apply = step.elapsed();
+  log_apply_duration(options, durations.apply);
+  options.emit(ProgressEvent::ApplyCompleted);
+
+  let step = Instant::now();
+  let outputs = read_outputs(work_dir, options)?;
+  durations.output = step.elapsed();
+  save_outputs(options, &outputs)?;
+  run_post_deploy_hook(options, &outputs)?;
+  options.emit(ProgressEvent::OutputsReady);
+  durations.total = started.elapsed();
+  Ok(DeployResult::new(
+    outputs,
+    work_dir.to_path_buf(),
+    durations,
+    counts,
+  ))
+}
+
+/// Validate Terraform config at `file` without touching any state.
+///
+/// Runs `terraform init -backend=false` first when the work directory has
+/// not been initialized yet. An invalid configuration is reported as
+/// [`Error::Validation`], listing Terraform's diagnostics.
+pub fn validate<P: AsRef<Path>>(
+  file: P,
+  log: impl Into<LogConfig>,
+) -> Result<()> {
+  let options = DeployOptions::new().log(log.into());
+  validate_with_options(file, &options)
+}
+
+/// Validate Terraform config at `file` as configured by `options`.
+///
+/// Variables in `options` are ignored, as `terraform validate` does not
+/// accept them.
+pub fn validate_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<()> {
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+
+  if !options.resolved_data_dir(&work_dir).exists() || options.init_upgrade {
+    options.log.info("Initializing Terraform without backend...");
+    let mut cmd = init_command(&work_dir, options);
+    cmd.arg("-backend=false");
+    let handlers = line_handlers(options, Step::Init);
+    let deadline = step_deadline(deadline, options.init_timeout);
+    operation_span!("terraform_init", &work_dir, options)
+      .in_scope(|| run_command(cmd, "terraform init", deadline, handlers))?;
+  }
+
+  run_validate(&work_dir, options)
+}
+
+/// `terraform validate -json` in `work_dir`, with stdout captured.
+fn validate_command(work_dir: &Path, options: &DeployOptions) -> Command {
+  let mut cmd = terraform_command(work_dir, options, Step::Init);
+  cmd.arg("validate").arg("-json").stdout(Stdio::piped());
+  cmd
+}
+
+/// Validate the initialized config in `work_dir`.
+fn run_validate(work_dir: &Path, options: &DeployOptions) -> Result<()> {
+  options.log.info("Validating Terraform...");
+  let output =
+    validate_command(work_dir, options)
+      .output()
+      .map_err(|source| Error::CommandIo {
+        command: "terraform validate -json".to_string(),
+        source,
+      })?;
+  check_validation(&output.stdout)
+}
+
+/// Async counterpart of [`run_validate`].
+#[cfg(feature = "async")]
+async fn run_validate_async(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<()> {
+  options.log.info("Validating Terraform...");
+  let output = validate_command(work_dir, options);
+  let output = tokio::process::Command::from(output)
     .output()
-    .context("Failed to execute `terraform output -json`")?;
-  if !output.status.success() {
-    bail!(
-      "`terraform output -json` failed with exit code {}",
-      output.status
-    );
-  }
-  let raw: HashMap<String, Value> = serde_json::from_slice(&output.stdout)
-    .context("Failed to parse Terraform output JSON")?;
-  let mut results = HashMap::new();
-  for (key, val) in raw {
-    if let Some(inner) = val.get("value") {
-      let s = if inner.is_string() {
-        inner.as_str().unwrap().to_string()
-      } else {
-        inner.to_string()
-      };
-      results.insert(key, s);
+    .await
+    .map_err(|source| Error::CommandIo {
+      command: "terraform validate -json".to_string(),
+      source,
+    })?;
+  check_validation(&output.stdout)
+}
+
+/// Turn the stdout of `terraform validate -json` into an
+/// [`Error::Validation`] if it reports the config invalid, logging any
+/// warnings otherwise.
+fn check_validation(stdout: &[u8]) -> Result<()> {
+  let parsed = validate::parse_validation(stdout)?;
+  if !parsed.valid {
+    return Err(ValidationError {
+      diagnostics: parsed.diagnostics,
     }
+    .into());
+  }
+  for diagnostic in &parsed.diagnostics {
+    tracing::warn!("{}", diagnostic);
   }
-  Ok(results)
+  Ok(())
+}
+
+/// Bring the existing resource `resource_id` under management at
+/// `resource_address` in the state of Terraform config at `file`.
+///
+/// Runs `terraform init` first when the work directory has not been
+/// initialized yet. `resource_address` must look like a resource address,
+/// e.g. `aws_instance.web`.
+pub fn import_resource<P: AsRef<Path>>(
+  file: P,
+  resource_address: &str,
+  resource_id: &str,
+  vars: &HashMap<String, String>,
+  log: impl Into<LogConfig>,
+) -> Result<()> {
+  let options = DeployOptions::new().vars(vars.clone()).log(log.into());
+  import_resource_with_options(file, resource_address, resource_id, &options)
+}
+
+/// Import `resource_id` at `resource_address` for Terraform config at
+/// `file` as configured by `options`.
+///
+/// Targets and replacements in `options` are ignored, as `terraform import`
+/// does not accept them.
+pub fn import_resource_with_options<P: AsRef<Path>>(
+  file: P,
+  resource_address: &str,
+  resource_id: &str,
+  options: &DeployOptions,
+) -> Result<()> {
+  if resource_address.is_empty() || !resource_address.contains('.') {
+    return Err(Error::InvalidOptions(format!(
+      "Invalid resource address `{}`, expected e.g. `aws_instance.web`",
+      resource_address
+    )));
+  }
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+  let _vars_file = write_vars_file(&work_dir, options)?;
+
+  init_if_needed(&work_dir, options, deadline)?;
+  select_workspace(&work_dir, options, deadline)?;
+
+  options
+    .log
+    .info(format_args!("Importing {}...", resource_address));
+  let mut cmd = terraform_command(&work_dir, options, Step::Apply);
+  cmd.arg("import");
+  push_var_args(&mut cmd, &work_dir, options)?;
+  cmd.arg(resource_address).arg(resource_id);
+  run_command(
+    cmd,
+    "terraform import",
+    deadline,
+    line_handlers(options, Step::Apply),
+  )
+}
+
+/// Run the read-only Terraform command `args` against the state of the
+/// config at `file`, returning its stdout.
+///
+/// The configured workspace is selected through `TF_WORKSPACE`, so that it
+/// is never created as a side effect.
+fn run_state_query<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+  args: &[&str],
+) -> Result<Vec<u8>> {
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+  run_state_query_in(&work_dir, options, args, deadline)
+}
+
+/// Run `terraform <args>` in the prepared `work_dir`, returning its stdout.
+fn run_state_query_in(
+  work_dir: &Path,
+  options: &DeployOptions,
+  args: &[&str],
+  deadline: Option<Instant>,
+) -> Result<Vec<u8>> {
+  let mut cmd = terraform_command(work_dir, options, Step::Init);
+  cmd.args(args);
+  if let Some(workspace) = &options.workspace {
+    cmd.env("TF_WORKSPACE", workspace);
+  }
+  let name = format!("terraform {}", args.join(" "));
+  run_command_capture(
+    cmd,
+    &name,
+    deadline,
+    line_handlers(options, Step::Init).stderr,
+  )
+}
+
+/// List the addresses of every resource in the state of Terraform config
+/// at `file`.
+pub fn state_list<P: AsRef<Path>>(
+  file: P,
+  log: impl Into<LogConfig>,
+) -> Result<Vec<String>> {
+  state_list_with_options(file, &DeployOptions::new().log(log.into()))
+}
+
+/// Like [`state_list`], running Terraform as configured by `options`.
+pub fn state_list_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<Vec<String>> {
+  let stdout = run_state_query(file, options, &["state", "list"])?;
+  Ok(state::parse_state_list(&stdout))
+}
+
+/// Report how far the Terraform config at `file` has been deployed.
+///
+/// Only inspects the existing work directory, which is never created or
+/// refreshed from the sources.
+pub fn status<P: AsRef<Path>>(file: P) -> Result<DeploymentStatus> {
+  status_with_options(file, &DeployOptions::new())
+}
+
+/// Like [`status`], with the work directory located and Terraform run as
+/// configured by `options`.
+///
+/// Resources are counted from `terraform state pull`, so remote backends
+/// are covered too.
+pub fn status_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<DeploymentStatus> {
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let src_dir = source_dir(file.as_ref())?;
+  let work_dir =
+    workspace::work_dir_path(&src_dir, &options.resolved_work_root());
+  if !work_dir.is_dir() {
+    return Ok(DeploymentStatus::NotInitialized);
+  }
+  let _lock = workspace::lock_work_dir(&work_dir, options.lock_timeout)?;
+  let data_dir = options.resolved_data_dir(&work_dir);
+  let initialized = fs::read_dir(&data_dir)
+    .map(|mut entries| entries.next().is_some())
+    .unwrap_or(false);
+  if !initialized {
+    return Ok(DeploymentStatus::NotInitialized);
+  }
+  let stdout =
+    run_state_query_in(&work_dir, options, &["state", "pull"], deadline)?;
+  state::deployment_status(&stdout)
+}
+
+/// Show the attributes of the resource at `resource_address` in the state
+/// of Terraform config at `file`.
+///
+/// String attributes are returned as is, any other value as JSON. Fails
+/// with [`Error::ResourceNotFound`] if the state has no such resource.
+pub fn state_show<P: AsRef<Path>>(
+  file: P,
+  resource_address: &str,
+  log: impl Into<LogConfig>,
+) -> Result<HashMap<String, String>> {
+  let options = DeployOptions::new().log(log.into());
+  state_show_with_options(file, resource_address, &options)
+}
+
+/// Like [`state_show`], running Terraform as configured by `options`.
+pub fn state_show_with_options<P: AsRef<Path>>(
+  file: P,
+  resource_address: &str,
+  options: &DeployOptions,
+) -> Result<HashMap<String, String>> {
+  let stdout = run_state_query(file, options, &["show", "-json"])?;
+  state::resource_attributes(&stdout, resource_address)?.ok_or_else(|| {
+    Error::ResourceNotFound {
+      address: resource_address.to_string(),
+    }
+  })
+}
+
+/// Move the resource at `source` to `destination` in the state of
+/// Terraform config at `file`, without destroying or recreating it.
+///
+/// Runs `terraform init` first when the work directory has not been
+/// initialized yet.
+pub fn state_move<P: AsRef<Path>>(
+  file: P,
+  source: &str,
+  destination: &str,
+  log: impl Into<LogConfig>,
+) -> Result<()> {
+  let options = DeployOptions::new().log(log.into());
+  state_move_with_options(file, source, destination, &options)
+}
+
+/// Like [`state_move`], running Terraform as configured by `options`.
+pub fn state_move_with_options<P: AsRef<Path>>(
+  file: P,
+  source: &str,
+  destination: &str,
+  options: &DeployOptions,
+) -> Result<()> {
+  if source.is_empty() || destination.is_empty() {
+    return Err(Error::InvalidOptions(
+      "Moving a resource requires both a source and a destination address"
+        .to_string(),
+    ));
+  }
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+
+  init_if_needed(&work_dir, options, deadline)?;
+  select_workspace(&work_dir, options, deadline)?;
+
+  options
+    .log
+    .info(format_args!("Moving {} to {}...", source, destination));
+  let mut cmd = terraform_command(&work_dir, options, Step::Apply);
+  cmd.arg("state").arg("mv").arg(source).arg(destination);
+  run_command(
+    cmd,
+    "terraform state mv",
+    deadline,
+    line_handlers(options, Step::Apply),
+  )
+}
+
+/// Remove the resources at `addresses` from the state of Terraform config
+/// at `file`, leaving the real infrastructure untouched.
+///
+/// Runs `terraform init` first when the work directory has not been
+/// initialized yet.
+pub fn state_remove<P: AsRef<Path>>(
+  file: P,
+  addresses: &[String],
+  log: impl Into<LogConfig>,
+) -> Result<()> {
+  let options = DeployOptions::new().log(log.into());
+  state_remove_with_options(file, addresses, &options)
+}
+
+/// Like [`state_remove`], running Terraform as configured by `options`.
+pub fn state_remove_with_options<P: AsRef<Path>>(
+  file: P,
+  addresses: &[String],
+  options: &DeployOptions,
+) -> Result<()> {
+  if addresses.is_empty() || addresses.iter().any(String::is_empty) {
+    return Err(Error::InvalidOptions(
+      "Removing resources from state requires at least one address".to_string(),
+    ));
+  }
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+
+  init_if_needed(&work_dir, options, deadline)?;
+  select_workspace(&work_dir, options, deadline)?;
+
+  options.log.info(format_args!(
+    "Removing {} from state...",
+    addresses.join(", ")
+  ));
+  let mut cmd = terraform_command(&work_dir, options, Step::Apply);
+  cmd.arg("state").arg("rm").args(addresses);
+  run_command(
+    cmd,
+    "terraform state rm",
+    deadline,
+    line_handlers(options, Step::Apply),
+  )
+}
+
+/// Whether `id` has the `8-4-4-4-12` hex digit layout of a UUID.
+fn looks_like_uuid(id: &str) -> bool {
+  let groups: Vec<&str> = id.split('-').collect();
+  groups.len() == 5
+    && groups.iter().zip([8, 4, 4, 4, 12]).all(|(group, len)| {
+      group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// Release the state lock `lock_id` of Terraform config at `file`, as left
+/// behind by a process that died while holding it.
+///
+/// Terraform's confirmation prompt is skipped, so make sure no other
+/// process is still using the state.
+pub fn force_unlock<P: AsRef<Path>>(
+  file: P,
+  lock_id: &str,
+  log: impl Into<LogConfig>,
+) -> Result<()> {
+  let options = DeployOptions::new().log(log.into());
+  force_unlock_with_options(file, lock_id, &options)
+}
+
+/// Like [`force_unlock`], running Terraform as configured by `options`.
+pub fn force_unlock_with_options<P: AsRef<Path>>(
+  file: P,
+  lock_id: &str,
+  options: &DeployOptions,
+) -> Result<()> {
+  if !looks_like_uuid(lock_id) {
+    return Err(Error::InvalidOptions(format!(
+      "Invalid state lock ID `{}`, expected a UUID",
+      lock_id
+    )));
+  }
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+
+  init_if_needed(&work_dir, options, deadline)?;
+  select_workspace(&work_dir, options, deadline)?;
+
+  options
+    .log
+    .info(format_args!("Releasing state lock {}...", lock_id));
+  let mut cmd = terraform_command(&work_dir, options, Step::Apply);
+  cmd.arg("force-unlock").arg("-force").arg(lock_id);
+  run_command(
+    cmd,
+    "terraform force-unlock",
+    deadline,
+    line_handlers(options, Step::Apply),
+  )
 }
 
 /// Destroy Terraform config at `file` with provided `vars`.
 pub fn undeploy<P: AsRef<Path>>(
   file: P,
   vars: &HashMap<String, String>,
-  debug: bool,
+  log: impl Into<LogConfig>,
 ) -> Result<()> {
-  ensure_terraform_installed()?;
-  let file = file
-    .as_ref()
-    .canonicalize()
-    .context("Failed to canonicalize Terraform path")?;
-  let src_dir = file
-    .parent()
-    .context("Cannot determine Terraform directory")?;
-  let work_dir = prepare_work_dir(src_dir)?;
+  let options = DeployOptions::new().vars(vars.clone()).log(log.into());
+  undeploy_with_options(file, &options)
+}
 
-  println!("Destroying Terraform...");
+/// Destroy Terraform config at `file` as configured by `options`.
+pub fn undeploy_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<()> {
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let (work_dir, _lock) = resolve_work_dir(file.as_ref(), options)?;
+  undeploy_in(&work_dir, options, deadline)
+}
+
+/// Destroy what was deployed from the prepared and locked `work_dir`.
+fn undeploy_in(
+  work_dir: &Path,
+  options: &DeployOptions,
+  deadline: Option<Instant>,
+) -> Result<()> {
+  let _vars_file = prepare_undeploy(work_dir, options)?;
+
+  select_workspace(work_dir, options, deadline)?;
+  options.log.info("Destroying Terraform...");
+  destroy_with_retry(work_dir, options, deadline)?;
+  options.log.info("All resources have been destroyed.");
+  finish_undeploy(work_dir, options)
+}
+
+/// Run the `pre_destroy` hook in `work_dir` and write the vars file there.
+fn prepare_undeploy(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<Option<VarsFile>> {
+  run_hook("pre_destroy", options.pre_destroy.as_ref(), work_dir)?;
+  write_vars_file(work_dir, options)
+}
 
-  let mut cmd = Command::new("terraform");
-  cmd.current_dir(&work_dir).arg("destroy").arg("-auto-approve");
-  for (k, v) in vars {
-    cmd.arg("-var").arg(format!("{}={}", k, v));
+/// Run the `post_destroy` hook in `work_dir`, then remove it if cleanup is
+/// enabled.
+fn finish_undeploy(work_dir: &Path, options: &DeployOptions) -> Result<()> {
+  run_hook("post_destroy", options.post_destroy.as_ref(), work_dir)?;
+  if options.cleanup {
+    workspace::remove_work_dir(work_dir)?;
   }
-  if !debug {
-    cmd.stdout(Stdio::null()).stderr(Stdio::null());
+  Ok(())
+}
+
+/// Remove the work directory of the Terraform config at `file`.
+///
+/// Deletes the copied sources, provider cache and state without running
+/// Terraform, so any resources still deployed are forgotten. Does nothing if
+/// no work directory exists.
+pub fn clean<P: AsRef<Path>>(file: P) -> Result<()> {
+  clean_with_options(file, &DeployOptions::new())
+}
+
+/// Remove the work directory of the Terraform config at `file`, located as
+/// configured by `options`.
+pub fn clean_with_options<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<()> {
+  let src_dir = source_dir(file.as_ref())?;
+  let work_dir =
+    workspace::work_dir_path(&src_dir, &options.resolved_work_root());
+  if !work_dir.is_dir() {
+    return Ok(());
   }
-  let status = cmd
-    .status()
-    .context("Failed to execute `terraform destroy`")?;
-  if !status.success() {
-    bail!("`terraform destroy` failed with exit code {}", status);
+  let _lock = workspace::lock_work_dir(&work_dir, options.lock_timeout)?;
+  options.log.info(format_args!(
+    "Removing temporary directory {}",
+    work_dir.display()
+  ));
+  workspace::remove_work_dir(&work_dir)
+}
+
+/// Remove the work directories of every configuration left untouched for
+/// `older_than`, returning their paths.
+///
+/// Work directories in use by another atar process are skipped.
+pub fn clean_workspaces(older_than: Duration) -> Result<Vec<PathBuf>> {
+  clean_workspaces_with_options(older_than, &DeployOptions::new())
+}
+
+/// Remove stale work directories under the work root configured by
+/// `options`.
+pub fn clean_workspaces_with_options(
+  older_than: Duration,
+  options: &DeployOptions,
+) -> Result<Vec<PathBuf>> {
+  let root = options.resolved_work_root();
+  let removed = workspace::remove_stale_work_dirs(&root, older_than)?;
+  for work_dir in &removed {
+    options.log.info(format_args!(
+      "Removed temporary directory {}",
+      work_dir.display()
+    ));
   }
-  println!("All resources have been destroyed.");
-  Ok(())
+  Ok(removed)
+}
+
+/// Describe every work directory under the default work root, least
+/// recently used first.
+pub fn list_workspaces() -> Result<Vec<WorkspaceInfo>> {
+  list_workspaces_with_options(&DeployOptions::new())
+}
+
+/// Describe every work directory under the work root configured by
+/// `options`.
+pub fn list_workspaces_with_options(
+  options: &DeployOptions,
+) -> Result<Vec<WorkspaceInfo>> {
+  workspace::list_work_dirs(&options.resolved_work_root())
+}
+
+/// Run `terraform workspace <subcommand> <args>` in `work_dir`, returning
+/// its stdout.
+fn run_workspace_command(
+  work_dir: &Path,
+  options: &DeployOptions,
+  subcommand: &str,
+  args: &[&str],
+) -> Result<Vec<u8>> {
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &ensure_tf_binary_installed(options)?;
+  let _lock = workspace::lock_work_dir(work_dir, options.lock_timeout)?;
+  let mut cmd = terraform_command(work_dir, options, Step::Init);
+  cmd.arg("workspace").arg(subcommand).args(args);
+  run_command_capture(
+    cmd,
+    &format!("terraform workspace {}", subcommand),
+    deadline,
+    line_handlers(options, Step::Init).stderr,
+  )
+}
+
+/// Create the Terraform workspace `name` in `work_dir` and switch to it.
+///
+/// `work_dir` is a work directory as returned in [`DeployResult`].
+pub fn workspace_new(work_dir: &Path, name: &str) -> Result<()> {
+  workspace_new_with_options(work_dir, name, &DeployOptions::new())
+}
+
+/// Like [`workspace_new`], running Terraform as configured by `options`.
+pub fn workspace_new_with_options(
+  work_dir: &Path,
+  name: &str,
+  options: &DeployOptions,
+) -> Result<()> {
+  run_workspace_command(work_dir, options, "new", &[name]).map(drop)
+}
+
+/// Switch `work_dir` to the existing Terraform workspace `name`.
+pub fn workspace_select(work_dir: &Path, name: &str) -> Result<()> {
+  workspace_select_with_options(work_dir, name, &DeployOptions::new())
+}
+
+/// Like [`workspace_select`], running Terraform as configured by `options`.
+pub fn workspace_select_with_options(
+  work_dir: &Path,
+  name: &str,
+  options: &DeployOptions,
+) -> Result<()> {
+  run_workspace_command(work_dir, options, "select", &[name]).map(drop)
+}
+
+/// List the Terraform workspaces of `work_dir`, `default` included.
+pub fn workspace_list(work_dir: &Path) -> Result<Vec<String>> {
+  workspace_list_with_options(work_dir, &DeployOptions::new())
+}
+
+/// Like [`workspace_list`], running Terraform as configured by `options`.
+pub fn workspace_list_with_options(
+  work_dir: &Path,
+  options: &DeployOptions,
+) -> Result<Vec<String>> {
+  let stdout = run_workspace_command(work_dir, options, "list", &[])?;
+  // The current workspace is marked with a leading `*`.
+  Ok(
+    String::from_utf8_lossy(&stdout)
+      .lines()
+      .map(|line| line.trim_start_matches('*').trim())
+      .filter(|name| !name.is_empty())
+      .map(str::to_string)
+      .collect(),
+  )
+}
+
+/// Delete the Terraform workspace `name` from `work_dir`.
+///
+/// Terraform refuses to delete a workspace that still tracks resources
+/// unless `force` is set, in which case those resources are left running
+/// but forgotten.
+pub fn workspace_delete(
+  work_dir: &Path,
+  name: &str,
+  force: bool,
+) -> Result<()> {
+  workspace_delete_with_options(work_dir, name, force, &DeployOptions::new())
+}
+
+/// Like [`workspace_delete`], running Terraform as configured by `options`.
+pub fn workspace_delete_with_options(
+  work_dir: &Path,
+  name: &str,
+  force: bool,
+  options: &DeployOptions,
+) -> Result<()> {
+  let args: &[&str] = if force { &["-force", name] } else { &[name] };
+  run_workspace_command(work_dir, options, "delete", args).map(drop)
+}
+
+/// Async counterpart of [`deploy`], driving Terraform through
+/// `tokio::process::Command`.
+///
+/// Must be awaited inside a tokio runtime.
+#[cfg(feature = "async")]
+pub async fn deploy_async<P: AsRef<Path>>(
+  file: P,
+  vars: &HashMap<String, String>,
+  log: impl Into<LogConfig>,
+) -> Result<HashMap<String, String>> {
+  let options = DeployOptions::new().vars(vars.clone()).log(log.into());
+  deploy_with_options_async(file, &options)
+    .await
+    .map(|result| output::stringify_outputs(result.outputs))
+}
+
+/// Async counterpart of [`deploy_with_options`].
+///
+/// Must be awaited inside a tokio runtime.
+#[cfg(feature = "async")]
+pub async fn deploy_with_options_async<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<DeployResult> {
+  let started = Instant::now();
+  let deadline = options.timeout.map(|t| started + t);
+  let options = &run_blocking(options, ensure_tf_binary_installed).await?;
+  let (work_dir, _lock) =
+    resolve_work_dir_async(file.as_ref(), options).await?;
+  let dir = work_dir.clone();
+  let _vars_file =
+    run_blocking(options, move |options| prepare_deploy(&dir, options))
+      .await?;
+  let mut durations = StepDurations::default();
+
+  options.emit(ProgressEvent::InitStarted);
+  let step = Instant::now();
+  run_init_async(&work_dir, options, deadline).await?;
+  durations.init = step.elapsed();
+  options.emit(ProgressEvent::InitCompleted);
+  select_workspace_async(&work_dir, options, deadline).await?;
+  if options.validate {
+    run_validate_async(&work_dir, options).await?;
+  }
+
+  options.log.info("Applying Terraform...");
+  options.emit(ProgressEvent::ApplyStarted);
+  let step = Instant::now();
+  let counts = apply_with_retry_async(&work_dir, options, deadline)
+    .instrument(operation_span!("terraform_apply", &work_dir, options))
+    .await?;
+  durations.apply = step.elapsed();
+  log_apply_duration(options, durations.apply);
+  options.emit(ProgressEvent::ApplyCompleted);
+
+  let step = Instant::now();
+  let outputs = read_outputs_async(&work_dir, options).await?;
+  durations.output = step.elapsed();
+  let outputs = run_blocking(options, move |options| {
+    publish_outputs(options, &outputs).map(|()| outputs)
+  })
+  .await?;
+  options.emit(ProgressEvent::OutputsReady);
+  durations.total = started.elapsed();
+  Ok(DeployResult::new(outputs, work_dir, durations, counts))
+}
+
+/// Async counterpart of [`undeploy`], driving Terraform through
+/// `tokio::process::Command`.
+///
+/// Must be awaited inside a tokio runtime.
+#[cfg(feature = "async")]
+pub async fn undeploy_async<P: AsRef<Path>>(
+  file: P,
+  vars: &HashMap<String, String>,
+  log: impl Into<LogConfig>,
+) -> Result<()> {
+  let options = DeployOptions::new().vars(vars.clone()).log(log.into());
+  undeploy_with_options_async(file, &options).await
+}
+
+/// Async counterpart of [`undeploy_with_options`].
+///
+/// Must be awaited inside a tokio runtime.
+#[cfg(feature = "async")]
+pub async fn undeploy_with_options_async<P: AsRef<Path>>(
+  file: P,
+  options: &DeployOptions,
+) -> Result<()> {
+  let deadline = options.timeout.map(|t| Instant::now() + t);
+  let options = &run_blocking(options, ensure_tf_binary_installed).await?;
+  let (work_dir, _lock) =
+    resolve_work_dir_async(file.as_ref(), options).await?;
+  let dir = work_dir.clone();
+  let _vars_file =
+    run_blocking(options, move |options| prepare_undeploy(&dir, options))
+      .await?;
+
+  select_workspace_async(&work_dir, options, deadline).await?;
+  options.log.info("Destroying Terraform...");
+  destroy_with_retry_async(&work_dir, options, deadline)
+    .instrument(operation_span!("terraform_destroy", &work_dir, options))
+    .await?;
+  options.log.info("All resources have been destroyed.");
+  run_blocking(options, move |options| finish_undeploy(&work_dir, options))
+    .await
 }
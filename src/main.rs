@@ -1,223 +1,1538 @@
 use anyhow::{bail, Context, Result};
-use atar::{deploy as lib_deploy, undeploy as lib_undeploy};
+use atar::{
+  clean_with_options as lib_clean,
+  clean_workspaces_with_options as lib_clean_workspaces,
+  deploy_with_options as lib_deploy,
+  import_resource_with_options as lib_import,
+  list_workspaces_with_options as lib_list_workspaces,
+  plan_with_options as lib_plan, status_with_options as lib_status,
+  undeploy_with_options as lib_undeploy, validate_with_options as lib_validate,
+  AtarError, Config, DeployOptions, DeployResult, DeploymentStatus, Diagnostic,
+  Engine, ProgressEvent, RetryPolicy, TerraformOutput, TfLogLevel,
+  WorkspaceInfo,
+};
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use serde_json::{json, Map, Value};
+#[cfg(windows)]
+use signal_hook::consts::signal::{SIGINT, SIGTERM};
+#[cfg(unix)]
 use signal_hook::{
-  consts::signal::{SIGINT, SIGTERM},
+  consts::signal::{SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2},
   iterator::Signals,
 };
 use std::panic;
+#[cfg(unix)]
+use std::thread;
 use std::{
-  collections::HashMap, env, path::PathBuf, process, sync::mpsc, thread,
+  collections::{HashMap, HashSet},
+  env, io,
+  path::PathBuf,
+  process,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+  },
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::{
+  fmt::{
+    format::{FmtSpan, Writer},
+    writer::BoxMakeWriter,
+    FmtContext, FormatEvent, FormatFields,
+  },
+  registry::LookupSpan,
 };
 
+/// Exit code for failures without a more specific code below.
+const EXIT_FAILURE: i32 = 1;
+/// Exit code when no usable Terraform binary was found.
+const EXIT_NOT_INSTALLED: i32 = 2;
+/// Exit code when `terraform init` failed.
+const EXIT_INIT_FAILED: i32 = 3;
+/// Exit code when `terraform apply` failed.
+const EXIT_APPLY_FAILED: i32 = 4;
+/// Exit code when `terraform destroy` failed.
+const EXIT_DESTROY_FAILED: i32 = 5;
+/// Exit code when the outputs could not be read or written.
+const EXIT_OUTPUT_FAILED: i32 = 6;
+/// Exit code when `atar status` finds no Terraform state.
+const EXIT_NO_STATE: i32 = 7;
+
+/// Default age, in seconds, past which `atar clean` removes work
+/// directories.
+const DEFAULT_CLEAN_AGE_SECS: u64 = 86400;
+
+/// Default number of `terraform destroy` attempts, so a rate limit hit while
+/// cleaning up does not leave resources behind.
+const DEFAULT_DESTROY_ATTEMPTS: u32 = 3;
+
+/// Environment variable naming the Terraform binary when neither `--binary`
+/// nor `--engine` is given.
+const BINARY_ENV: &str = "TERRAFORM_BINARY";
+
+/// Signals that end a deployment unless `--signals` says otherwise.
+#[cfg(unix)]
+const DEFAULT_SIGNALS: &[i32] = &[SIGINT, SIGTERM, SIGHUP];
+#[cfg(windows)]
+const DEFAULT_SIGNALS: &[i32] = &[SIGINT];
+
+/// Signals `--signals` accepts, by name without the `SIG` prefix.
+#[cfg(unix)]
+const SIGNAL_NAMES: &[(&str, i32)] = &[
+  ("INT", SIGINT),
+  ("TERM", SIGTERM),
+  ("HUP", SIGHUP),
+  ("QUIT", SIGQUIT),
+  ("USR1", SIGUSR1),
+  ("USR2", SIGUSR2),
+];
+#[cfg(windows)]
+const SIGNAL_NAMES: &[(&str, i32)] = &[("INT", SIGINT), ("TERM", SIGTERM)];
+
+/// Help text listing the exit codes, shown after the top-level help.
+const EXIT_CODES_HELP: &str = "\
+EXIT CODES:
+  0    Success
+  1    Invalid arguments, or any other failure
+  2    Terraform is not installed or too old
+  3    terraform init failed
+  4    terraform apply failed
+  5    terraform destroy failed
+  6    Outputs could not be read or written
+  7    status found no Terraform state
+  130  Exited on a second signal without destroying";
+
+/// Ephemeral Terraform runner: applies on start, auto-destroys on exit or
+/// failure.
+#[derive(Parser)]
+#[command(
+  version,
+  after_help = EXIT_CODES_HELP,
+  arg_required_else_help = true
+)]
+struct Cli {
+  /// Log the Terraform commands run and how long each step took
+  #[arg(long, global = true)]
+  debug: bool,
+  /// Print results to stdout as a single JSON object and errors to stderr
+  /// as {"error": "..."}
+  #[arg(long, global = true)]
+  json: bool,
+  #[command(subcommand)]
+  command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+  /// Deploy a Terraform module, wait until interrupted, then destroy it
+  Deploy(DeployArgs),
+  /// Destroy an existing Terraform deployment
+  Undeploy(UndeployArgs),
+  /// Show what deploy would change, without creating any resources
+  Plan(PlanArgs),
+  /// Check a Terraform module with `terraform validate`, without touching
+  /// any state
+  Validate(ModuleArgs),
+  /// Remove the temporary work directory of a Terraform module, state
+  /// included, without destroying anything
+  ///
+  /// Without --terraform-path, removes every work directory unused for
+  /// --older-than seconds.
+  Clean(CleanArgs),
+  /// Import an existing resource into the Terraform state
+  Import(ImportArgs),
+  /// Report whether a Terraform module is deployed and with how many
+  /// resources, without changing anything
+  Status(StatusArgs),
+  /// List the temporary work directories atar has created, least recently
+  /// used first
+  Workspaces,
+  /// Inspect the config file holding default flags
+  ///
+  /// Read from $ATAR_CONFIG, else .atar.toml in the current directory,
+  /// else ~/.config/atar/config.toml. Flags override its values.
+  #[command(subcommand)]
+  Config(ConfigCommands),
+  /// Print a completion script for SHELL to stdout
+  ///
+  /// For example, with bash: atar completions --shell bash >
+  /// ~/.local/share/bash-completion/completions/atar
+  Completions(CompletionsArgs),
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+  /// Check that the config file parses and has no unknown keys
+  Validate,
+}
+
+/// Flags locating a Terraform module and the binary that runs it.
+#[derive(Args)]
+struct ModuleArgs {
+  /// Terraform `main.tf` or its directory (default: from the config file)
+  #[arg(long, short = 't', visible_alias = "terraform", value_name = "PATH")]
+  terraform_path: Option<PathBuf>,
+  /// terraform, tofu or a binary path
+  #[arg(
+    long,
+    alias = "terraform-bin",
+    alias = "terraform-binary",
+    value_name = "NAME"
+  )]
+  binary: Option<PathBuf>,
+  /// terraform or tofu (default: auto-detect)
+  #[arg(long)]
+  engine: Option<Engine>,
+  /// Provider cache shared between modules
+  #[arg(long, value_name = "DIR")]
+  plugin_cache: Option<PathBuf>,
+}
+
+impl ModuleArgs {
+  /// The module path, and options running the chosen binary, falling
+  /// back to `config` for what the flags leave out.
+  ///
+  /// Without `--binary` or `--engine`, [`BINARY_ENV`] names the binary,
+  /// then `config`.
+  fn into_options(
+    self,
+    debug: bool,
+    config: &Config,
+  ) -> Result<(PathBuf, DeployOptions)> {
+    let path = self
+      .terraform_path
+      .or_else(|| config.terraform_path.clone())
+      .context("`--terraform-path` argument is required")?;
+    let mut options = DeployOptions::new().debug(debug);
+    if let Some(engine) = self.engine {
+      options = options.engine(engine);
+    }
+    let bin = self.binary.or_else(|| {
+      let from_env = env::var_os(BINARY_ENV).filter(|bin| !bin.is_empty());
+      from_env
+        .map(PathBuf::from)
+        .or_else(|| config.binary.clone())
+        .filter(|_| self.engine.is_none())
+    });
+    if let Some(bin) = bin {
+      options = options.terraform_bin(bin);
+    }
+    if let Some(dir) = self.plugin_cache {
+      options = options.plugin_cache_dir(dir);
+    }
+    if let Some(version) = &config.min_version {
+      options = options.min_terraform_version(version.clone());
+    }
+    Ok((path, options))
+  }
+}
+
+/// Flags shared by every subcommand that initializes a Terraform module.
+#[derive(Args)]
+struct TerraformArgs {
+  #[command(flatten)]
+  module: ModuleArgs,
+  /// Terraform `.tfvars` file (repeatable)
+  #[arg(long, value_name = "PATH")]
+  var_file: Vec<PathBuf>,
+  /// Terraform workspace, created if missing
+  #[arg(long, value_name = "NAME")]
+  workspace: Option<String>,
+  /// Backend setting for init (repeatable)
+  #[arg(long, value_name = "K=V", value_parser = parse_key_value)]
+  backend_config: Vec<(String, String)>,
+  /// Backend settings file (repeatable)
+  #[arg(long, value_name = "PATH")]
+  backend_config_file: Vec<PathBuf>,
+  /// Upgrade providers and modules on init
+  #[arg(long)]
+  upgrade: bool,
+  /// Init a changed backend, ignoring state
+  #[arg(long)]
+  reconfigure: bool,
+  /// Init a changed backend, moving state
+  #[arg(long)]
+  migrate_state: bool,
+  /// Terraform data dir (default: .terraform)
+  #[arg(long, value_name = "DIR")]
+  data_dir: Option<PathBuf>,
+  /// Leave matching files out of the copy (repeatable)
+  #[arg(long, value_name = "PATTERN")]
+  ignore: Vec<String>,
+  /// Terraform variable (repeatable); `--<var> <value>` is short for
+  /// `--var <var>=<value>`
+  #[arg(
+    long = "var",
+    value_name = "NAME=VALUE",
+    value_parser = parse_key_value
+  )]
+  vars: Vec<(String, String)>,
+  /// Pass variables in a file, hidden from ps
+  #[arg(long)]
+  vars_via_file: bool,
+  /// Read variables from env vars with PREFIX
+  #[arg(long, value_name = "PREFIX")]
+  env_prefix: Option<String>,
+  /// Variables from a .env file (repeatable)
+  #[arg(long, value_name = "PATH")]
+  env_file: Vec<PathBuf>,
+  /// Terraform log level, trace to error
+  #[arg(long, value_name = "LEVEL")]
+  tf_log: Option<TfLogLevel>,
+  /// File Terraform writes its log to
+  #[arg(long, value_name = "PATH")]
+  tf_log_path: Option<PathBuf>,
+}
+
+impl TerraformArgs {
+  /// The module path, and options carrying every flag on top of the
+  /// variables and var-files of `config`.
+  fn into_options(
+    self,
+    debug: bool,
+    config: &Config,
+  ) -> Result<(PathBuf, DeployOptions)> {
+    let (path, mut options) = self.module.into_options(debug, config)?;
+    options = options.vars(config.vars.clone());
+    for file in config.var_files.iter().chain(&self.var_file) {
+      options = options.var_file(file);
+    }
+    if let Some(name) = self.workspace {
+      options = options.workspace(name);
+    }
+    for file in self.backend_config_file {
+      options = options.backend_config_file(file);
+    }
+    for (key, value) in self.backend_config {
+      options = options.backend_config(key, value);
+    }
+    if let Some(dir) = self.data_dir {
+      options = options.data_dir(dir);
+    }
+    for pattern in self.ignore {
+      options = options.ignore(pattern);
+    }
+    for (key, value) in self.vars {
+      options = options.var(key, value);
+    }
+    if let Some(prefix) = self.env_prefix {
+      options = options.env_var_prefix(prefix);
+    }
+    for file in self.env_file {
+      options = options.env_file(file);
+    }
+    if let Some(level) = self.tf_log {
+      options = options.tf_log_level(level);
+    }
+    if let Some(path) = self.tf_log_path {
+      options = options.tf_log_path(path);
+    }
+    let options = options
+      .init_upgrade(self.upgrade)
+      .init_reconfigure(self.reconfigure)
+      .init_migrate_state(self.migrate_state)
+      .vars_via_file(self.vars_via_file);
+    Ok((path, options))
+  }
+}
+
+/// Flags shaping a plan, apply or destroy.
+#[derive(Args)]
+struct RunArgs {
+  /// Limit to this resource (repeatable)
+  #[arg(long, value_name = "RESOURCE")]
+  target: Vec<String>,
+  /// Concurrent operations (default: 10)
+  #[arg(long, value_name = "N")]
+  parallelism: Option<u32>,
+  /// Do not lock the Terraform state
+  #[arg(long)]
+  no_lock: bool,
+}
+
+impl RunArgs {
+  /// Add the flags to `options`, falling back to `config` for what they
+  /// leave out.
+  fn apply(self, mut options: DeployOptions, config: &Config) -> DeployOptions {
+    for resource in self.target {
+      options = options.target(resource);
+    }
+    if let Some(parallelism) = self.parallelism.or(config.parallelism) {
+      options = options.parallelism(parallelism);
+    }
+    if self.no_lock {
+      options = options.lock(false);
+    }
+    options
+  }
+}
+
+#[derive(Args)]
+struct DeployArgs {
+  #[command(flatten)]
+  terraform: TerraformArgs,
+  #[command(flatten)]
+  run: RunArgs,
+  /// Recreate this resource (repeatable)
+  #[arg(long, value_name = "RESOURCE")]
+  replace: Vec<String>,
+  /// Remove the work directory after destroy
+  #[arg(long)]
+  cleanup: bool,
+  /// Destroy attempts on transient errors
+  #[arg(long, value_name = "N", default_value_t = DEFAULT_DESTROY_ATTEMPTS)]
+  destroy_attempts: u32,
+  /// Retry a failed apply up to N times, whatever the error
+  #[arg(long, value_name = "N")]
+  retries: Option<u32>,
+  /// Wait before the first apply retry, doubling after each
+  #[arg(long, value_name = "SECONDS", requires = "retries")]
+  retry_backoff: Option<u64>,
+  /// Exit after deploying, leaving resources up
+  #[arg(long)]
+  keep: bool,
+  /// Run `terraform validate` before applying
+  #[arg(long)]
+  validate: bool,
+  /// Only sync state with reality, then exit
+  #[arg(long)]
+  refresh_only: bool,
+  /// Also write the outputs there as JSON
+  #[arg(long, value_name = "PATH")]
+  output_file: Option<PathBuf>,
+  /// Comma-separated signals that destroy (default: INT,TERM,HUP)
+  #[arg(long, value_name = "LIST")]
+  signals: Option<String>,
+  /// Passed on to `terraform apply` as is
+  #[arg(last = true, value_name = "ARGS")]
+  extra_args: Vec<String>,
+}
+
+#[derive(Args)]
+struct UndeployArgs {
+  #[command(flatten)]
+  terraform: TerraformArgs,
+  #[command(flatten)]
+  run: RunArgs,
+  /// Remove the work directory after destroy
+  #[arg(long)]
+  cleanup: bool,
+  /// Destroy attempts on transient errors
+  #[arg(long, value_name = "N", default_value_t = DEFAULT_DESTROY_ATTEMPTS)]
+  destroy_attempts: u32,
+  /// Passed on to `terraform destroy` as is
+  #[arg(last = true, value_name = "ARGS")]
+  extra_args: Vec<String>,
+}
+
+#[derive(Args)]
+struct PlanArgs {
+  #[command(flatten)]
+  terraform: TerraformArgs,
+  #[command(flatten)]
+  run: RunArgs,
+  /// Recreate this resource (repeatable)
+  #[arg(long, value_name = "RESOURCE")]
+  replace: Vec<String>,
+}
+
+#[derive(Args)]
+struct CleanArgs {
+  /// Terraform `main.tf` or its directory
+  #[arg(
+    long,
+    short = 't',
+    visible_alias = "terraform",
+    value_name = "PATH",
+    conflicts_with = "older_than"
+  )]
+  terraform_path: Option<PathBuf>,
+  /// Minimum age of removed directories
+  #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_CLEAN_AGE_SECS)]
+  older_than: u64,
+}
+
+#[derive(Args)]
+struct StatusArgs {
+  #[command(flatten)]
+  module: ModuleArgs,
+  /// Terraform workspace to check
+  #[arg(long, value_name = "NAME")]
+  workspace: Option<String>,
+  /// Terraform data dir (default: .terraform)
+  #[arg(long, value_name = "DIR")]
+  data_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct CompletionsArgs {
+  /// Shell to complete for
+  #[arg(long, value_enum)]
+  shell: Shell,
+}
+
+#[derive(Args)]
+struct ImportArgs {
+  #[command(flatten)]
+  terraform: TerraformArgs,
+  /// Resource address, e.g. aws_instance.web
+  #[arg(long, value_name = "ADDR")]
+  address: String,
+  /// Provider-specific ID of the resource
+  #[arg(long, value_name = "ID")]
+  id: String,
+}
+
 fn main() {
-  run().unwrap_or_else(|err| {
-    eprintln!("Error: {}\nRun with --debug flag for more information", err);
-    process::exit(1);
+  let (args, warnings) = expand_var_shorthand(env::args().collect())
+    .unwrap_or_else(|err| {
+      eprintln!("error: {}", err);
+      process::exit(EXIT_FAILURE);
+    });
+  let cli = Cli::try_parse_from(&args).unwrap_or_else(|err| {
+    // Usage errors exit with 1 rather than clap's 2, which means Terraform
+    // is missing. Help asked for by running `atar` alone is no error.
+    let _ = err.print();
+    let help = !err.use_stderr()
+      || err.kind() == ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand;
+    process::exit(if help { 0 } else { EXIT_FAILURE });
+  });
+  let json = cli.json;
+  run(cli, &args, &warnings).unwrap_or_else(|err| {
+    if json {
+      eprintln!("{}", json!({ "error": err.to_string() }));
+    } else {
+      eprintln!("Error: {}\nRun with --debug flag for more information", err);
+    }
+    process::exit(exit_code(&err));
   })
 }
 
-fn run() -> Result<()> {
-  let mut args: Vec<String> = env::args().collect();
-  let debug = args.iter().any(|a| a == "--debug");
-  args.retain(|a| a != "--debug");
-  if args.len() <= 1 || args[1] == "-h" || args[1] == "--help" {
-    print_help();
-    return Ok(());
+/// Exit code telling which stage `err` comes from, so scripts can e.g.
+/// retry only failed applies.
+fn exit_code(err: &anyhow::Error) -> i32 {
+  match err.downcast_ref::<AtarError>() {
+    Some(
+      AtarError::TerraformNotInstalled { .. }
+      | AtarError::TerraformVersionTooOld { .. },
+    ) => EXIT_NOT_INSTALLED,
+    Some(AtarError::InitFailed { .. }) => EXIT_INIT_FAILED,
+    Some(AtarError::ApplyFailed { .. }) => EXIT_APPLY_FAILED,
+    Some(AtarError::DestroyFailed { .. }) => EXIT_DESTROY_FAILED,
+    Some(
+      AtarError::OutputParseFailed(_) | AtarError::OutputFileFailed { .. },
+    ) => EXIT_OUTPUT_FAILED,
+    Some(
+      AtarError::CommandFailed { command, .. }
+      | AtarError::CommandIo { command, .. }
+      | AtarError::Timeout {
+        operation: command, ..
+      },
+    ) => command_exit_code(command),
+    _ => EXIT_FAILURE,
   }
-  if args[1] == "--version" {
-    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+}
+
+/// Exit code for a failure of the Terraform `command`, e.g.
+/// `terraform output -json`.
+fn command_exit_code(command: &str) -> i32 {
+  let subcommand = command.split_whitespace().nth(1).unwrap_or_default();
+  match subcommand {
+    "init" => EXIT_INIT_FAILED,
+    "apply" => EXIT_APPLY_FAILED,
+    "destroy" => EXIT_DESTROY_FAILED,
+    "output" => EXIT_OUTPUT_FAILED,
+    _ => EXIT_FAILURE,
+  }
+}
+
+fn run(cli: Cli, cli_args: &[String], warnings: &[String]) -> Result<()> {
+  // Completions need no config, so a broken config file cannot block them
+  if let Commands::Completions(args) = &cli.command {
+    write_completions(args.shell, &mut io::stdout());
     return Ok(());
   }
-  if args[1] == "deploy" {
-    if args.len() >= 3 && (args[2] == "-h" || args[2] == "--help") {
-      print_deploy_help();
-      return Ok(());
-    }
-    let mut terraform_file_path: Option<PathBuf> = None;
-    let mut vars: HashMap<String, String> = HashMap::new();
-    let mut i = 2;
-    while i < args.len() {
-      match args[i].as_str() {
-        "--terraform-path" | "-t" => {
-          i += 1;
-          if i >= args.len() {
-            bail!("--terraform-path requires a path");
-          }
-          terraform_file_path = Some(PathBuf::from(&args[i]));
-        }
-        arg if arg.starts_with("--") => {
-          let key = arg.trim_start_matches("--").to_string();
-          i += 1;
-          if i >= args.len() {
-            bail!("Flag {} requires a value", arg);
-          }
-          vars.insert(key, args[i].clone());
-        }
-        other => bail!("Unexpected argument: {}", other),
+  let config_path = Config::locate();
+  let config = match &config_path {
+    Some(path) => Config::load(path)?,
+    None => Config::default(),
+  };
+  let debug = cli.debug || config.debug == Some(true);
+  let json = cli.json;
+  init_logging(debug, json);
+  for warning in warnings {
+    tracing::warn!("{}", warning);
+  }
+  match cli.command {
+    Commands::Deploy(args) => {
+      let signals = match &args.signals {
+        Some(names) => parse_signals(names)?,
+        None => DEFAULT_SIGNALS.to_vec(),
+      };
+      let (tf_file_path, options) =
+        args.terraform.into_options(debug, &config)?;
+      let mut options = args.run.apply(options, &config);
+      for resource in args.replace {
+        options = options.replace(resource);
       }
-      i += 1;
-    }
-    let tf_file_path =
-      terraform_file_path.context("`--terraform-path` argument is required")?;
-    return run_deploy(tf_file_path, vars, debug);
-  }
-  if args[1] == "undeploy" {
-    if args.len() >= 3 && (args[2] == "-h" || args[2] == "--help") {
-      print_undeploy_help();
-      return Ok(());
-    }
-    let mut terraform_file_path: Option<PathBuf> = None;
-    let mut vars: HashMap<String, String> = HashMap::new();
-    let mut i = 2;
-    while i < args.len() {
-      match args[i].as_str() {
-        "--terraform-path" | "-t" => {
-          i += 1;
-          if i >= args.len() {
-            bail!("--terraform-path requires a path");
-          }
-          terraform_file_path = Some(PathBuf::from(&args[i]));
-        }
-        arg if arg.starts_with("--") => {
-          let key = arg.trim_start_matches("--").to_string();
-          i += 1;
-          if i >= args.len() {
-            bail!("Flag {} requires a value", arg);
-          }
-          vars.insert(key, args[i].clone());
+      if let Some(path) = args.output_file {
+        options = options.output_file(path);
+      }
+      if let Some(retries) = args.retries {
+        options = options.retries(retries);
+      }
+      if let Some(secs) = args.retry_backoff {
+        options = options.retry_backoff(Duration::from_secs(secs));
+      }
+      let options = options
+        .cleanup(args.cleanup)
+        .destroy_retry(destroy_retry(args.destroy_attempts))
+        .validate(args.validate)
+        .refresh_only(args.refresh_only);
+      // The extra args are meant for `terraform apply` only
+      let teardown = Teardown {
+        options: options.clone(),
+        command: undeploy_command(cli_args),
+      };
+      run_deploy(
+        tf_file_path,
+        options.extra_args(args.extra_args),
+        teardown,
+        &signals,
+        args.keep,
+        args.refresh_only,
+        json,
+      )
+    }
+    Commands::Undeploy(args) => {
+      let (tf_file_path, options) =
+        args.terraform.into_options(debug, &config)?;
+      let options = args
+        .run
+        .apply(options, &config)
+        .cleanup(args.cleanup)
+        .destroy_retry(destroy_retry(args.destroy_attempts))
+        .extra_args(args.extra_args);
+      run_undeploy(tf_file_path, options, json)
+    }
+    Commands::Plan(args) => {
+      let (tf_file_path, options) =
+        args.terraform.into_options(debug, &config)?;
+      let mut options = args.run.apply(options, &config);
+      for resource in args.replace {
+        options = options.replace(resource);
+      }
+      run_plan(tf_file_path, options, json)
+    }
+    Commands::Validate(args) => {
+      let (tf_file_path, options) = args.into_options(debug, &config)?;
+      run_validate(tf_file_path, options, json)
+    }
+    Commands::Clean(args) => {
+      let options = DeployOptions::new().debug(debug);
+      match args.terraform_path {
+        Some(tf_file_path) => lib_clean(&tf_file_path, &options)?,
+        None => {
+          let older_than = Duration::from_secs(args.older_than);
+          lib_clean_workspaces(older_than, &options)?;
         }
-        other => bail!("Unexpected argument: {}", other),
       }
-      i += 1;
+      Ok(())
+    }
+    Commands::Workspaces => {
+      let options = DeployOptions::new().debug(debug);
+      run_workspaces(&lib_list_workspaces(&options)?, json)
+    }
+    Commands::Status(args) => {
+      let (tf_file_path, mut options) =
+        args.module.into_options(debug, &config)?;
+      if let Some(name) = args.workspace {
+        options = options.workspace(name);
+      }
+      if let Some(dir) = args.data_dir {
+        options = options.data_dir(dir);
+      }
+      run_status(tf_file_path, options, json)
+    }
+    Commands::Config(ConfigCommands::Validate) => {
+      let path = config_path.context(
+        "No config file found: set ATAR_CONFIG, or create .atar.toml or \
+         ~/.config/atar/config.toml",
+      )?;
+      if json {
+        println!("{}", json!({ "path": path, "valid": true }));
+      } else {
+        println!("{} is valid.", path.display());
+      }
+      Ok(())
+    }
+    Commands::Completions(_) => unreachable!("handled before the config"),
+    Commands::Import(args) => {
+      let (tf_file_path, options) =
+        args.terraform.into_options(debug, &config)?;
+      Ok(lib_import(
+        &tf_file_path,
+        &args.address,
+        &args.id,
+        &options,
+      )?)
     }
-    let tf_file_path =
-      terraform_file_path.context("`--terraform-path` argument is required")?;
-    return run_undeploy(tf_file_path, vars, debug);
   }
-  eprintln!("Unknown command: {}", args[1]);
-  print_help();
-  process::exit(1);
 }
 
-fn print_help() {
-  println!(
-    "{} {}\n{}\n\n\
-     USAGE:\n\n\
-     atar [--debug] deploy --terraform-path <PATH> [--<var> <value> ...]\n\n\
-     If undeploy fails when exiting, run:\n\n\
-     atar [--debug] undeploy --terraform-path <PATH> [--<var> <value> ...]\n\n\
-     For help on the `deploy` subcommand, run:\natar deploy --help\n\n\
-     For help on the `undeploy` subcommand, run:\natar undeploy --help",
-    env!("CARGO_PKG_NAME"),
-    env!("CARGO_PKG_VERSION"),
-    env!("CARGO_PKG_DESCRIPTION"),
-  );
+/// Print the library's progress messages as plain lines on stdout, adding
+/// the Terraform commands being run with `--debug`.
+///
+/// With `--json` they go to stderr instead, leaving stdout to the result.
+fn init_logging(debug: bool, json: bool) {
+  let writer = if json {
+    BoxMakeWriter::new(io::stderr)
+  } else {
+    BoxMakeWriter::new(io::stdout)
+  };
+  let builder = tracing_subscriber::fmt().with_writer(writer);
+  if debug {
+    // Show which Terraform operation each line belongs to, and how long
+    // each operation took; span timings need the timer.
+    builder
+      .with_max_level(Level::DEBUG)
+      .with_span_events(FmtSpan::CLOSE)
+      .with_level(false)
+      .with_target(false)
+      .init();
+  } else {
+    builder
+      .with_max_level(Level::INFO)
+      .event_format(MessageOnly)
+      .init();
+  }
 }
 
-fn print_deploy_help() {
-  println!(
-        "atar deploy\n\n\
-         Deploys a Terraform module, waits until interrupted, then destroys it.\n\n\
-         USAGE:\n  atar deploy --terraform <PATH> [--<var> <value> ...]\n\n\
-         FLAGS:\n  --terraform <PATH>    Path to Terraform `main.tf` file\n  \
-         --<var> <value>       Terraform variable\n"
+/// Event format printing only the message and fields of each event, without
+/// the spans it happened in.
+struct MessageOnly;
+
+impl<S, N> FormatEvent<S, N> for MessageOnly
+where
+  S: Subscriber + for<'a> LookupSpan<'a>,
+  N: for<'a> FormatFields<'a> + 'static,
+{
+  fn format_event(
+    &self,
+    ctx: &FmtContext<'_, S, N>,
+    mut writer: Writer<'_>,
+    event: &Event<'_>,
+  ) -> std::fmt::Result {
+    ctx.format_fields(writer.by_ref(), event)?;
+    writeln!(writer)
+  }
+}
+
+/// Rewrite every `--<name> <value>` pair naming no flag of the subcommand
+/// into `--var <name>=<value>`, for subcommands taking variables.
+///
+/// Also returns a warning for each such value that looks like a flag. A name
+/// a typo away from a flag is rejected rather than taken as a variable.
+fn expand_var_shorthand(
+  args: Vec<String>,
+) -> Result<(Vec<String>, Vec<String>)> {
+  let cli = Cli::command();
+  let subcommand = args
+    .iter()
+    .skip(1)
+    .find(|arg| !arg.starts_with('-'))
+    .and_then(|name| cli.find_subcommand(name))
+    .filter(|sub| sub.get_arguments().any(|a| a.get_long() == Some("var")));
+  let Some(subcommand) = subcommand else {
+    return Ok((args, Vec::new()));
+  };
+  let known: HashSet<&str> = cli
+    .get_arguments()
+    .chain(subcommand.get_arguments())
+    .flat_map(|arg| {
+      let aliases = arg.get_all_aliases().unwrap_or_default();
+      arg.get_long().into_iter().chain(aliases)
+    })
+    .chain(["help"])
+    .collect();
+  let mut expanded = Vec::with_capacity(args.len());
+  let mut warnings = Vec::new();
+  let mut args = args.into_iter();
+  while let Some(arg) = args.next() {
+    if arg == "--" {
+      expanded.push(arg);
+      expanded.extend(args);
+      break;
+    }
+    let key = match arg.strip_prefix("--") {
+      Some(key) if !key.contains('=') && !known.contains(key) => key,
+      _ => {
+        expanded.push(arg);
+        continue;
+      }
+    };
+    if let Some(flag) = similar_flag(key, &known) {
+      bail!(
+        "unexpected argument '--{}' found\n\n  \
+         tip: a similar argument exists: '--{}'\n  \
+         tip: to set a variable of that name, use '--var {}=<VALUE>'",
+        key,
+        flag,
+        key
+      );
+    }
+    let Some(value) = args.next() else {
+      expanded.push(arg);
+      break;
+    };
+    if value.starts_with("--") {
+      warnings.push(format!(
+        "Warning: value `{}` of variable `{}` looks like a flag; \
+         use --var {}={} if that is intended",
+        value, key, key, value
+      ));
+    }
+    expanded.push("--var".to_string());
+    expanded.push(format!("{}={}", key, value));
+  }
+  Ok((expanded, warnings))
+}
+
+/// The flag among `known` that `name` is most likely a typo of, if any.
+///
+/// Short flags only match one edit away, so e.g. a `key` variable is not
+/// mistaken for `--keep`.
+fn similar_flag<'a>(name: &str, known: &HashSet<&'a str>) -> Option<&'a str> {
+  known
+    .iter()
+    .map(|flag| (edit_distance(name, flag), *flag))
+    .filter(|&(distance, flag)| {
+      distance == 1 || (distance == 2 && flag.len() >= 6)
+    })
+    .min()
+    .map(|(_, flag)| flag)
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut row: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut diagonal = row[0];
+    row[0] = i + 1;
+    for (j, cb) in b.iter().enumerate() {
+      let substitution = diagonal + usize::from(ca != *cb);
+      diagonal = row[j + 1];
+      row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+    }
+  }
+  row[b.len()]
+}
+
+/// Parse a `KEY=VALUE` flag value.
+fn parse_key_value(arg: &str) -> Result<(String, String)> {
+  let (key, value) = arg.split_once('=').context("expected KEY=VALUE")?;
+  Ok((key.to_string(), value.to_string()))
+}
+
+/// Parse a comma-separated list of signal names such as `INT,SIGHUP`.
+fn parse_signals(names: &str) -> Result<Vec<i32>> {
+  names
+    .split(',')
+    .map(|name| {
+      let name = name.trim().to_ascii_uppercase();
+      let name = name.strip_prefix("SIG").unwrap_or(&name);
+      SIGNAL_NAMES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|&(_, signal)| signal)
+        .with_context(|| format!("Unsupported signal `{}`", name))
+    })
+    .collect()
+}
+
+/// `SIG`-prefixed name of `signal`, as listed in [`SIGNAL_NAMES`].
+#[cfg(unix)]
+fn signal_name(signal: i32) -> String {
+  SIGNAL_NAMES
+    .iter()
+    .find(|&&(_, known)| known == signal)
+    .map_or_else(|| signal.to_string(), |(name, _)| format!("SIG{}", name))
+}
+
+/// Render outputs as a JSON object, masking sensitive values like the
+/// human-readable listing does.
+fn outputs_json(outputs: &HashMap<String, TerraformOutput>) -> Value {
+  let outputs: Map<String, Value> = outputs
+    .iter()
+    .map(|(k, v)| {
+      let value = if v.sensitive {
+        Value::from("<sensitive>")
+      } else {
+        v.value.clone()
+      };
+      (k.clone(), value)
+    })
+    .collect();
+  Value::Object(outputs)
+}
+
+/// Print `workspaces` as a table, or as a JSON array with Unix timestamps.
+fn run_workspaces(workspaces: &[WorkspaceInfo], json: bool) -> Result<()> {
+  if json {
+    let unix = |time: SystemTime| {
+      time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+    };
+    let list: Vec<Value> = workspaces
+      .iter()
+      .map(|info| {
+        json!({
+          "path": info.path,
+          "created_at": unix(info.created_at),
+          "last_used": unix(info.last_used),
+          "source_dir": info.source_dir,
+          "size_bytes": info.size_bytes,
+        })
+      })
+      .collect();
+    println!("{}", Value::Array(list));
+    return Ok(());
+  }
+  if workspaces.is_empty() {
+    println!("No work directories.");
+    return Ok(());
+  }
+  println!("LAST USED  CREATED          SIZE  SOURCE");
+  for info in workspaces {
+    let source = match &info.source_dir {
+      Some(dir) => dir.display().to_string(),
+      None => "?".to_string(),
+    };
+    println!(
+      "{:<10} {:<10} {:>10}  {}\n  {}",
+      format_age(info.last_used),
+      format_age(info.created_at),
+      format_size(info.size_bytes),
+      source,
+      info.path.display()
     );
+  }
+  Ok(())
 }
 
-fn print_undeploy_help() {
-  println!(
-    "atar undeploy\n\n\
-         Destroys an existing Terraform deployment.\n\n\
-         USAGE:\n  atar undeploy --terraform <PATH> [--<var> <value> ...]\n\n\
-         FLAGS:\n  --terraform <PATH>    Path to Terraform `main.tf` file\n  \
-         --<var> <value>       Terraform variable\n"
-  );
+/// How long ago `time` was, in its largest whole unit, e.g. `3h ago`.
+fn format_age(time: SystemTime) -> String {
+  let secs = time.elapsed().unwrap_or_default().as_secs();
+  match secs {
+    0..60 => "just now".to_string(),
+    60..3600 => format!("{}m ago", secs / 60),
+    3600..86400 => format!("{}h ago", secs / 3600),
+    _ => format!("{}d ago", secs / 86400),
+  }
+}
+
+/// `bytes` in the largest binary unit that keeps it at least 1.
+fn format_size(bytes: u64) -> String {
+  const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+  let mut size = bytes as f64;
+  let mut unit = 0;
+  while size >= 1024.0 && unit + 1 < UNITS.len() {
+    size /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{} B", bytes)
+  } else {
+    format!("{:.1} {}", size, UNITS[unit])
+  }
+}
+
+/// Print the outputs of a deploy, or with `json` everything it returned as
+/// a single JSON object.
+fn print_deploy_result(result: &DeployResult, json: bool) {
+  if json {
+    println!(
+      "{}",
+      json!({
+        "outputs": outputs_json(&result.outputs),
+        "work_dir": result.work_dir,
+        "durations": {
+          "init": result.durations.init.as_secs_f64(),
+          "apply": result.durations.apply.as_secs_f64(),
+          "output": result.durations.output.as_secs_f64(),
+          "total": result.durations.total.as_secs_f64(),
+        },
+        "changed": result.changed,
+        "changes": {
+          "added": result.changes.added,
+          "changed": result.changes.changed,
+          "destroyed": result.changes.destroyed,
+        },
+      })
+    );
+  } else if !result.outputs.is_empty() {
+    println!("*************************** Outputs **************************");
+    // `TerraformOutput` displays sensitive values as `<sensitive>`
+    for (k, v) in &result.outputs {
+      println!("{}: {}", k, v);
+    }
+    println!("**************************************************************");
+  }
+}
+
+/// Write the completion script of `shell` to `out`.
+fn write_completions(shell: Shell, out: &mut dyn io::Write) {
+  let mut command = Cli::command();
+  let name = command.get_name().to_string();
+  clap_complete::generate(shell, &mut command, name, out);
+}
+
+/// Retry policy making up to `attempts` destroy attempts.
+fn destroy_retry(attempts: u32) -> RetryPolicy {
+  RetryPolicy {
+    max_attempts: attempts,
+    ..RetryPolicy::default()
+  }
+}
+
+/// The `atar undeploy` command destroying what the `atar deploy` command
+/// line `args` deployed, quoted for a POSIX shell.
+fn undeploy_command(args: &[String]) -> String {
+  let deploy_only = deploy_only_flags();
+  let mut command = vec!["atar".to_string()];
+  let mut args = args.iter().skip(1);
+  let mut in_subcommand = false;
+  while let Some(arg) = args.next() {
+    // Global flags take no value, so the first other word is `deploy`
+    if !in_subcommand && !arg.starts_with('-') {
+      command.push("undeploy".to_string());
+      in_subcommand = true;
+      continue;
+    }
+    // What follows `--` is meant for `terraform apply`
+    if arg == "--" {
+      break;
+    }
+    let (name, inline_value) = match arg.split_once('=') {
+      Some((name, _)) => (name, true),
+      None => (arg.as_str(), false),
+    };
+    match name
+      .strip_prefix("--")
+      .and_then(|name| deploy_only.get(name))
+    {
+      Some(&takes_value) => {
+        if takes_value && !inline_value {
+          args.next();
+        }
+      }
+      None => command.push(shell_quote(arg)),
+    }
+  }
+  command.join(" ")
+}
+
+/// Long flags of `atar deploy`, aliases included, that `atar undeploy` does
+/// not take, and whether each is followed by a value.
+fn deploy_only_flags() -> HashMap<String, bool> {
+  let cli = Cli::command();
+  let (Some(deploy), Some(undeploy)) = (
+    cli.find_subcommand("deploy"),
+    cli.find_subcommand("undeploy"),
+  ) else {
+    return HashMap::new();
+  };
+  let long_names = |arg: &clap::Arg| -> Vec<String> {
+    let aliases = arg.get_all_aliases().unwrap_or_default();
+    arg
+      .get_long()
+      .into_iter()
+      .chain(aliases)
+      .map(String::from)
+      .collect()
+  };
+  let kept: HashSet<String> =
+    undeploy.get_arguments().flat_map(long_names).collect();
+  deploy
+    .get_arguments()
+    .flat_map(|arg| {
+      let takes_value = arg.get_action().takes_values();
+      long_names(arg)
+        .into_iter()
+        .map(move |name| (name, takes_value))
+    })
+    .filter(|(name, _)| !kept.contains(name))
+    .collect()
+}
+
+/// Quote `arg` for a POSIX shell unless it is safe as is.
+fn shell_quote(arg: &str) -> String {
+  let safe = !arg.is_empty()
+    && arg
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c));
+  if safe {
+    arg.to_string()
+  } else {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+  }
+}
+
+/// Print `notice` for the user, on stderr with `json` so stdout stays
+/// parseable.
+fn print_notice(notice: &str, json: bool) {
+  if json {
+    eprintln!("{}", notice);
+  } else {
+    println!("{}", notice);
+  }
 }
 
 fn run_deploy(
   file: PathBuf,
-  vars: HashMap<String, String>,
-  debug: bool,
+  options: DeployOptions,
+  teardown: Teardown,
+  signals: &[i32],
+  keep: bool,
+  refresh_only: bool,
+  json: bool,
 ) -> Result<()> {
   // Log init/apply steps with file path and each variable on its own line
   // Print variables once, then show placeholders for init/apply
-  println!("Variables:");
-  println!("  path: {}", file.display());
-  for (k, v) in &vars {
-    println!("  {}: {}", k, v);
+  if !json {
+    println!("Variables:");
+    println!("  path: {}", file.display());
+    for (k, v) in options.variables() {
+      println!("  {}: {}", k, v);
+    }
   }
 
-  let outputs = lib_deploy(&file, &vars, debug)?;
-  if !outputs.is_empty() {
-    println!("*************************** Outputs **************************");
-    for (k, v) in outputs {
-      println!("{}: {}", k, v);
-    }
-    println!("**************************************************************");
+  // A refresh-only deploy changes nothing, so there is nothing to destroy
+  // on exit either.
+  if refresh_only {
+    let result = lib_deploy(&file, &options)?;
+    print_deploy_result(&result, json);
+    return Ok(());
   }
-  // Setup cleanup guard and panic hook (unwinding) after resources are deployed
+
+  if keep {
+    let result = lib_deploy(&file, &options)?;
+    print_deploy_result(&result, json);
+    print_notice(
+      &format!(
+        "Resources deployed and left running.\n\n\
+         Run `{}` to destroy them.",
+        teardown.command
+      ),
+      json,
+    );
+    return Ok(());
+  }
+
+  // Listen for signals and arm the cleanup guard before deploying: Ctrl+C
+  // also reaches Terraform, which stops the apply and fails the deploy, and
+  // whatever it created by then must be destroyed as well. A second signal
+  // gives up on the destroy and exits right away.
+  let rx = listen_for_signals(signals)?;
+  let armed = Arc::new(AtomicBool::new(false));
+  let options = {
+    let armed = armed.clone();
+    options.progress(move |event| {
+      if event == ProgressEvent::ApplyStarted {
+        armed.store(true, Ordering::SeqCst);
+      }
+    })
+  };
   let guard = DestroyGuard {
     file: file.clone(),
-    vars: vars.clone(),
-    debug,
+    teardown: teardown.clone(),
+    armed: armed.clone(),
   };
   {
     let fh = file.clone();
-    let vh = vars.clone();
-    let dbg = debug;
     let previous = panic::take_hook();
     panic::set_hook(Box::new(move |info| {
-      eprintln!("panic: {:?}, cleaning up Terraform...", info);
-      if let Err(err) = lib_undeploy(&fh, &vh, dbg) {
-        eprintln!("cleanup after panic failed: {}", err);
+      if armed.load(Ordering::SeqCst) {
+        eprintln!("panic: {:?}, cleaning up Terraform...", info);
+        if let Err(err) = lib_undeploy(&fh, &teardown.options) {
+          eprintln!(
+            "cleanup after panic failed: {}\n\n\
+             Run `{}` to finish the cleanup.",
+            err, teardown.command
+          );
+        }
       }
       previous(info);
     }));
   }
+
+  let result = match lib_deploy(&file, &options) {
+    Ok(result) => result,
+    Err(err) => {
+      if guard.armed.load(Ordering::SeqCst) {
+        tracing::info!(
+          "Deploy did not complete, destroying what was created..."
+        );
+      }
+      return Err(err.into());
+    }
+  };
+  print_deploy_result(&result, json);
+  print_notice(
+    &format!(
+      "Resources deployed.\n\n{} to destroy and exit.",
+      stop_hint(signals)
+    ),
+    json,
+  );
+  let _ = rx.recv();
+  tracing::info!("\nSignal received: starting Terraform destroy...");
+  drop(guard);
+  Ok(())
+}
+
+/// Start listening for `signals`, returning a channel that receives once
+/// the first arrives. The process exits on the second.
+#[cfg(unix)]
+fn listen_for_signals(signals: &[i32]) -> Result<mpsc::Receiver<()>> {
   let (tx, rx) = mpsc::channel();
-  let mut signals =
-    Signals::new(&[SIGINT, SIGTERM]).context("Failed to set signal handler")?;
+  let mut listener =
+    Signals::new(signals).context("Failed to set signal handler")?;
   thread::spawn(move || {
-    for _ in signals.forever() {
+    let mut received = listener.forever();
+    if received.next().is_some() {
       let _ = tx.send(());
-      break;
+    }
+    if received.next().is_some() {
+      exit_on_second_signal();
     }
   });
-  println!(
-    "Resources deployed.\n\nPress Ctrl+C or send SIGTERM to destroy and exit."
+  Ok(rx)
+}
+
+/// Start listening for Ctrl+C, returning a channel that receives once it
+/// is first pressed. The process exits on the second.
+///
+/// Windows has no POSIX signals: Ctrl+C, Ctrl+Break and closing the
+/// console all count, whatever `signals` lists.
+#[cfg(windows)]
+fn listen_for_signals(_signals: &[i32]) -> Result<mpsc::Receiver<()>> {
+  let (tx, rx) = mpsc::channel();
+  let received = AtomicBool::new(false);
+  ctrlc::set_handler(move || {
+    if received.swap(true, Ordering::SeqCst) {
+      exit_on_second_signal();
+    }
+    let _ = tx.send(());
+  })
+  .context("Failed to set signal handler")?;
+  Ok(rx)
+}
+
+/// Give up on the destroy after a second signal.
+fn exit_on_second_signal() -> ! {
+  eprintln!(
+    "\nSecond signal received: exiting without waiting for destroy. \
+     Resources may be left behind; run `atar undeploy` to remove them."
   );
-  let _ = rx.recv();
-  println!("\nSignal received: starting Terraform destroy...");
-  drop(guard);
-  Ok(())
+  process::exit(130);
+}
+
+/// How to end a deployment listening for `signals`, for the user.
+#[cfg(unix)]
+fn stop_hint(signals: &[i32]) -> String {
+  let names: Vec<String> = signals.iter().map(|&s| signal_name(s)).collect();
+  format!("Send {}", names.join(" or "))
+}
+
+/// How to end a deployment, for the user.
+#[cfg(windows)]
+fn stop_hint(_signals: &[i32]) -> String {
+  "Press Ctrl+C".to_string()
 }
 
 fn run_undeploy(
   file: PathBuf,
-  vars: HashMap<String, String>,
-  debug: bool,
+  options: DeployOptions,
+  json: bool,
 ) -> Result<()> {
   // Print variables once, then placeholder for destroy
-  println!("Variables:");
-  println!("  path: {}", file.display());
-  for (k, v) in &vars {
-    println!("  {}: {}", k, v);
+  if !json {
+    println!("Variables:");
+    println!("  path: {}", file.display());
+    for (k, v) in options.variables() {
+      println!("  {}: {}", k, v);
+    }
+  }
+
+  lib_undeploy(&file, &options)?;
+  Ok(())
+}
+
+/// Print how far the config at `file` is deployed, or with `json` a JSON
+/// object, exiting with [`EXIT_NO_STATE`] when it has no state.
+fn run_status(file: PathBuf, options: DeployOptions, json: bool) -> Result<()> {
+  let status = lib_status(&file, &options)?;
+  let (name, resource_count, message) = match status {
+    DeploymentStatus::NotInitialized => (
+      "not_initialized",
+      0,
+      "Not deployed: Terraform was never initialized for this module."
+        .to_string(),
+    ),
+    DeploymentStatus::Initialized => (
+      "initialized",
+      0,
+      "Not deployed: nothing has been applied yet.".to_string(),
+    ),
+    DeploymentStatus::Deployed { resource_count } => (
+      "deployed",
+      resource_count,
+      format!("Deployed: {} resources in the state.", resource_count),
+    ),
+    DeploymentStatus::Destroyed => (
+      "destroyed",
+      0,
+      "Destroyed: the state holds no resources.".to_string(),
+    ),
+    _ => ("unknown", 0, format!("Unknown status: {:?}", status)),
+  };
+  if json {
+    println!(
+      "{}",
+      json!({ "status": name, "resource_count": resource_count })
+    );
+  } else {
+    println!("{}", message);
+  }
+  if matches!(
+    status,
+    DeploymentStatus::NotInitialized | DeploymentStatus::Initialized
+  ) {
+    process::exit(EXIT_NO_STATE);
+  }
+  Ok(())
+}
+
+/// Validate the config at `file`; with `json`, print the verdict and any
+/// diagnostics as a JSON object.
+fn run_validate(
+  file: PathBuf,
+  options: DeployOptions,
+  json: bool,
+) -> Result<()> {
+  match lib_validate(&file, &options) {
+    Ok(()) => {
+      if json {
+        println!("{}", json!({ "valid": true, "diagnostics": [] }));
+      } else {
+        println!("Configuration is valid.");
+      }
+      Ok(())
+    }
+    Err(atar::Error::Validation(err)) if json => {
+      let diagnostics: Vec<Value> =
+        err.diagnostics.iter().map(diagnostic_json).collect();
+      println!("{}", json!({ "valid": false, "diagnostics": diagnostics }));
+      Err(atar::Error::Validation(err).into())
+    }
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// Render a validation diagnostic as JSON, mirroring Terraform's own
+/// layout.
+fn diagnostic_json(diagnostic: &Diagnostic) -> Value {
+  json!({
+    "severity": diagnostic.severity,
+    "summary": diagnostic.summary,
+    "detail": diagnostic.detail,
+    "range": diagnostic.range.as_ref().map(|range| json!({
+      "filename": range.filename,
+      "start": { "line": range.start_line, "column": range.start_column },
+      "end": { "line": range.end_line, "column": range.end_column },
+    })),
+  })
+}
+
+fn run_plan(file: PathBuf, options: DeployOptions, json: bool) -> Result<()> {
+  if !json {
+    println!("Variables:");
+    println!("  path: {}", file.display());
+    for (k, v) in options.variables() {
+      println!("  {}: {}", k, v);
+    }
   }
 
-  lib_undeploy(&file, &vars, debug)?;
+  let summary = lib_plan(&file, &options)?;
+  if json {
+    println!(
+      "{}",
+      json!({
+        "to_add": summary.to_add,
+        "to_change": summary.to_change,
+        "to_destroy": summary.to_destroy,
+        "plan_file": summary.plan_file,
+      })
+    );
+    return Ok(());
+  }
+  println!(
+    "Plan: {} to add, {} to change, {} to destroy.",
+    summary.to_add, summary.to_change, summary.to_destroy
+  );
+  println!("Saved plan: {}", summary.plan_file.display());
   Ok(())
 }
 
+/// How to destroy what `atar deploy` created: automatically with `options`,
+/// or by hand with `command` should that fail.
+#[derive(Clone)]
+struct Teardown {
+  options: DeployOptions,
+  command: String,
+}
+
+/// Destroys the deployment when dropped, once apply has started.
 struct DestroyGuard {
   file: PathBuf,
-  vars: HashMap<String, String>,
-  debug: bool,
+  teardown: Teardown,
+  armed: Arc<AtomicBool>,
 }
 
 impl Drop for DestroyGuard {
   fn drop(&mut self) {
-    lib_undeploy(&self.file, &self.vars, self.debug).unwrap_or_else(|err| {
-      eprintln!("Failed to destroy Terraform resources: {}", err);
+    if !self.armed.load(Ordering::SeqCst) {
+      return;
+    }
+    let Teardown { options, command } = &self.teardown;
+    lib_undeploy(&self.file, options).unwrap_or_else(|err| {
+      eprintln!(
+        "Failed to destroy Terraform resources: {}\n\n\
+         Run `{}` to finish the cleanup.",
+        err, command
+      );
     });
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use clap::ValueEnum;
+
+  fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+  }
+
+  #[test]
+  fn shorthand_becomes_a_variable() {
+    let (expanded, warnings) = expand_var_shorthand(args(&[
+      "atar", "deploy", "--region", "eu", "--key", "k",
+    ]))
+    .unwrap();
+    let expected = ["atar", "deploy", "--var", "region=eu", "--var", "key=k"];
+    assert_eq!(expanded, args(&expected));
+    assert!(warnings.is_empty());
+  }
+
+  #[test]
+  fn shorthand_close_to_a_flag_is_rejected() {
+    for typo in ["--debugg", "--workspce", "--vars"] {
+      let err = expand_var_shorthand(args(&["atar", "deploy", typo, "x"]));
+      assert!(err.is_err(), "{} was taken as a variable", typo);
+    }
+  }
+
+  #[test]
+  fn shorthand_value_looking_like_a_flag_warns() {
+    let (expanded, warnings) =
+      expand_var_shorthand(args(&["atar", "deploy", "--name", "--x"])).unwrap();
+    assert_eq!(expanded, args(&["atar", "deploy", "--var", "name=--x"]));
+    assert_eq!(warnings.len(), 1);
+  }
+
+  #[test]
+  fn edit_distance_counts_edits() {
+    assert_eq!(edit_distance("debug", "debug"), 0);
+    assert_eq!(edit_distance("debugg", "debug"), 1);
+    assert_eq!(edit_distance("key", "keep"), 2);
+    assert_eq!(edit_distance("", "var"), 3);
+  }
+
+  #[test]
+  fn undeploy_command_keeps_what_locates_the_deployment() {
+    let command = undeploy_command(&args(&[
+      "/usr/bin/atar",
+      "--json",
+      "deploy",
+      "-t",
+      "main.tf",
+      "--workspace",
+      "dev",
+      "--var",
+      "region=eu",
+    ]));
+    assert_eq!(
+      command,
+      "atar --json undeploy -t main.tf --workspace dev --var region=eu"
+    );
+  }
+
+  #[test]
+  fn undeploy_command_drops_deploy_only_flags() {
+    let command = undeploy_command(&args(&[
+      "atar",
+      "deploy",
+      "--keep",
+      "--replace",
+      "a.b",
+      "--output-file=out.json",
+      "--signals",
+      "INT,TERM",
+      "--validate",
+      "--refresh-only",
+      "--retries=2",
+      "--retry-backoff",
+      "5",
+      "--cleanup",
+      "-t",
+      "main.tf",
+      "--",
+      "-lock=false",
+    ]));
+    assert_eq!(command, "atar undeploy --cleanup -t main.tf");
+  }
+
+  #[test]
+  fn undeploy_command_quotes_for_the_shell() {
+    let command = undeploy_command(&args(&[
+      "atar",
+      "deploy",
+      "-t",
+      "my dir/main.tf",
+      "--var",
+      "msg=it's $HOME",
+      "--var",
+      "empty=",
+      "--var-file",
+      "",
+    ]));
+    assert_eq!(
+      command,
+      "atar undeploy -t 'my dir/main.tf' --var 'msg=it'\\''s $HOME' \
+       --var empty= --var-file ''"
+    );
+  }
+
+  #[test]
+  fn completions_are_generated_for_every_shell() {
+    for shell in Shell::value_variants() {
+      let mut out = Vec::new();
+      write_completions(*shell, &mut out);
+      assert!(!out.is_empty(), "no completions for {}", shell);
+    }
+  }
+}
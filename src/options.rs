@@ -0,0 +1,938 @@
+//! Options shared by `deploy` and `undeploy`.
+
+use crate::retry::DEFAULT_MAX_BACKOFF;
+use crate::{BackoffStrategy, Error, HookError, RetryPolicy};
+use std::{
+  collections::HashMap,
+  env, fmt,
+  path::{Path, PathBuf},
+  process::Command,
+  str::FromStr,
+  sync::Arc,
+  time::Duration,
+};
+
+/// Largest `-parallelism` accepted by [`DeployOptions::parallelism`].
+const MAX_PARALLELISM: u32 = 512;
+
+/// Environment variable overriding the directory work directories are
+/// created in.
+const WORK_ROOT_ENV: &str = "ATAR_WORK_ROOT";
+
+/// Environment variable Terraform reads its shared provider cache directory
+/// from.
+const PLUGIN_CACHE_ENV: &str = "TF_PLUGIN_CACHE_DIR";
+
+/// Prefix of the environment variables Terraform reads input variables
+/// from.
+const TF_VAR_PREFIX: &str = "TF_VAR_";
+
+/// Environment variable overriding where Terraform keeps providers, modules
+/// and the selected workspace.
+const TF_DATA_DIR_ENV: &str = "TF_DATA_DIR";
+
+/// Environment variable setting Terraform's own log level.
+const TF_LOG_ENV: &str = "TF_LOG";
+
+/// Environment variable naming the file Terraform writes its log to.
+const TF_LOG_PATH_ENV: &str = "TF_LOG_PATH";
+
+/// Terraform-compatible CLI used to run a configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+  /// HashiCorp Terraform, invoked as `terraform`.
+  Terraform,
+  /// OpenTofu, invoked as `tofu`.
+  OpenTofu,
+}
+
+impl Engine {
+  /// Name of the engine's binary as looked up in `PATH`.
+  pub fn binary_name(self) -> &'static str {
+    match self {
+      Engine::Terraform => "terraform",
+      Engine::OpenTofu => "tofu",
+    }
+  }
+}
+
+impl FromStr for Engine {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "terraform" => Ok(Engine::Terraform),
+      "tofu" | "opentofu" => Ok(Engine::OpenTofu),
+      other => Err(Error::InvalidOptions(format!(
+        "Unknown engine `{}`, expected terraform or tofu",
+        other
+      ))),
+    }
+  }
+}
+
+/// Verbosity of Terraform's internal log, passed as `TF_LOG`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TfLogLevel {
+  /// Everything, including provider RPC traffic.
+  Trace,
+  /// Debugging detail.
+  Debug,
+  /// Informational messages.
+  Info,
+  /// Warnings only.
+  Warn,
+  /// Errors only.
+  Error,
+}
+
+impl TfLogLevel {
+  /// Value of `TF_LOG` selecting this level.
+  pub fn as_str(self) -> &'static str {
+    match self {
+      TfLogLevel::Trace => "TRACE",
+      TfLogLevel::Debug => "DEBUG",
+      TfLogLevel::Info => "INFO",
+      TfLogLevel::Warn => "WARN",
+      TfLogLevel::Error => "ERROR",
+    }
+  }
+}
+
+impl FromStr for TfLogLevel {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "trace" => Ok(TfLogLevel::Trace),
+      "debug" => Ok(TfLogLevel::Debug),
+      "info" => Ok(TfLogLevel::Info),
+      "warn" => Ok(TfLogLevel::Warn),
+      "error" => Ok(TfLogLevel::Error),
+      _ => Err(Error::InvalidOptions(format!(
+        "Unknown Terraform log level `{}`, expected trace, debug, info, \
+         warn or error",
+        s
+      ))),
+    }
+  }
+}
+
+/// How much atar logs about its own progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+  /// Log nothing beyond warnings, e.g. retries and validation warnings.
+  Quiet,
+  /// Log an `info` event as each step starts, e.g. `Applying Terraform...`.
+  #[default]
+  Info,
+}
+
+/// What gets printed while Terraform runs.
+///
+/// Terraform's own output is shown per step, so noisy `init` output can be
+/// silenced while still watching `apply`. `LogConfig::from(debug)` matches
+/// the plain `debug` flag: every step shown when `true`, none when `false`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogConfig {
+  /// Show Terraform's output for `init` and `validate`.
+  pub show_init: bool,
+  /// Show Terraform's output for `plan` and `apply`.
+  pub show_apply: bool,
+  /// Show Terraform's output for `destroy`.
+  pub show_destroy: bool,
+  /// Which of atar's own messages to log.
+  pub level: LogLevel,
+}
+
+impl LogConfig {
+  /// Log `message` through `tracing` unless the level is
+  /// [`LogLevel::Quiet`].
+  pub(crate) fn info(&self, message: impl fmt::Display) {
+    if self.level >= LogLevel::Info {
+      tracing::info!("{}", message);
+    }
+  }
+}
+
+impl From<bool> for LogConfig {
+  fn from(debug: bool) -> Self {
+    LogConfig {
+      show_init: debug,
+      show_apply: debug,
+      show_destroy: debug,
+      level: LogLevel::Info,
+    }
+  }
+}
+
+/// A step of `deploy` reported to the progress callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+  /// `terraform init` is about to run.
+  InitStarted,
+  /// `terraform init` finished successfully.
+  InitCompleted,
+  /// `terraform apply` is about to run.
+  ApplyStarted,
+  /// A line of `terraform apply` output, as Terraform prints it without
+  /// `-json`. Only reported when apply output is shown or an output sink
+  /// is set.
+  ApplyLine(String),
+  /// `terraform apply` finished successfully.
+  ApplyCompleted,
+  /// Outputs have been read and the deploy is about to return.
+  OutputsReady,
+}
+
+/// Callback receiving [`ProgressEvent`]s.
+pub(crate) type ProgressFn = dyn Fn(ProgressEvent) + Send + Sync;
+
+/// Callback receiving lines of Terraform output.
+pub(crate) type OutputSinkFn = dyn Fn(&str) + Send + Sync;
+
+/// Hook receiving the work directory.
+pub(crate) type WorkDirHookFn =
+  dyn Fn(&Path) -> Result<(), HookError> + Send + Sync;
+
+/// Hook receiving the stringified outputs of a deploy.
+pub(crate) type OutputsHookFn =
+  dyn Fn(&HashMap<String, String>) -> Result<(), HookError> + Send + Sync;
+
+/// A caller-supplied callback, shared between clones of [`DeployOptions`].
+pub(crate) struct Callback<F: ?Sized>(pub(crate) Arc<F>);
+
+impl<F: ?Sized> Clone for Callback<F> {
+  fn clone(&self) -> Self {
+    Callback(Arc::clone(&self.0))
+  }
+}
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("Callback(..)")
+  }
+}
+
+/// Options controlling how Terraform is invoked.
+///
+/// Built with chained setters:
+///
+/// ```no_run
+/// use atar::DeployOptions;
+/// use std::time::Duration;
+///
+/// let options = DeployOptions::new()
+///   .var("region", "us-west-2")
+///   .var_file("prod.tfvars")
+///   .debug(true)
+///   .parallelism(10)
+///   .timeout(Duration::from_secs(600));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DeployOptions {
+  pub(crate) terraform_bin: Option<PathBuf>,
+  pub(crate) engine: Option<Engine>,
+  pub(crate) min_terraform_version: Option<semver::Version>,
+  pub(crate) work_root: Option<PathBuf>,
+  pub(crate) ignore_patterns: Vec<String>,
+  pub(crate) plugin_cache_dir: Option<PathBuf>,
+  pub(crate) data_dir: Option<PathBuf>,
+  pub(crate) tf_log_level: Option<TfLogLevel>,
+  pub(crate) tf_log_path: Option<PathBuf>,
+  pub(crate) output_file: Option<PathBuf>,
+  pub(crate) lock_timeout: Duration,
+  pub(crate) cleanup: bool,
+  pub(crate) validate: bool,
+  pub(crate) refresh_only: bool,
+  pub(crate) vars: HashMap<String, String>,
+  pub(crate) skip_tf_vars: bool,
+  pub(crate) env_var_prefix: Option<String>,
+  pub(crate) vars_via_file: bool,
+  pub(crate) var_files: Vec<PathBuf>,
+  pub(crate) env_files: Vec<PathBuf>,
+  /// Variables read from `env_files`, filled in before Terraform runs.
+  pub(crate) env_file_vars: HashMap<String, String>,
+  pub(crate) targets: Vec<String>,
+  pub(crate) workspace: Option<String>,
+  pub(crate) backend_config: Vec<(String, String)>,
+  pub(crate) backend_config_files: Vec<PathBuf>,
+  pub(crate) init_upgrade: bool,
+  pub(crate) init_reconfigure: bool,
+  pub(crate) init_migrate_state: bool,
+  pub(crate) replace_resources: Vec<String>,
+  pub(crate) extra_args: Vec<String>,
+  pub(crate) log: LogConfig,
+  pub(crate) parallelism: Option<u32>,
+  pub(crate) state_lock: Option<bool>,
+  pub(crate) timeout: Option<Duration>,
+  pub(crate) init_timeout: Option<Duration>,
+  pub(crate) apply_timeout: Option<Duration>,
+  pub(crate) destroy_timeout: Option<Duration>,
+  pub(crate) retry: RetryPolicy,
+  pub(crate) destroy_retry: RetryPolicy,
+  pub(crate) progress: Option<Callback<ProgressFn>>,
+  pub(crate) output_sink: Option<Callback<OutputSinkFn>>,
+  pub(crate) pre_deploy: Option<Callback<WorkDirHookFn>>,
+  pub(crate) post_deploy: Option<Callback<OutputsHookFn>>,
+  pub(crate) pre_destroy: Option<Callback<WorkDirHookFn>>,
+  pub(crate) post_destroy: Option<Callback<WorkDirHookFn>>,
+}
+
+impl DeployOptions {
+  /// Create options with Terraform's defaults.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Use the Terraform binary at `bin` instead of looking one up in `PATH`.
+  ///
+  /// Any command-compatible binary works, e.g. OpenTofu's `tofu`. Takes
+  /// precedence over [`DeployOptions::engine`].
+  pub fn terraform_bin(mut self, bin: impl Into<PathBuf>) -> Self {
+    self.terraform_bin = Some(bin.into());
+    self
+  }
+
+  /// Run the Terraform-compatible binary `name`, e.g. `"tofu"`, looked up
+  /// in `PATH` unless it is a path.
+  ///
+  /// Same as [`DeployOptions::terraform_bin`].
+  pub fn binary(self, name: impl Into<PathBuf>) -> Self {
+    self.terraform_bin(name)
+  }
+
+  /// Run `engine` instead of auto-detecting one.
+  ///
+  /// Without this, `terraform` is used if available and `tofu` otherwise.
+  pub fn engine(mut self, engine: Engine) -> Self {
+    self.engine = Some(engine);
+    self
+  }
+
+  /// Refuse to run Terraform older than `version`, failing with
+  /// [`crate::Error::TerraformVersionTooOld`] before anything else happens.
+  pub fn min_terraform_version(mut self, version: semver::Version) -> Self {
+    self.min_terraform_version = Some(version);
+    self
+  }
+
+  /// Create work directories under `root` instead of `$TMPDIR/atar`, e.g.
+  /// on a larger disk.
+  ///
+  /// Takes precedence over the `ATAR_WORK_ROOT` environment variable. Each
+  /// configuration still gets the same subdirectory, so existing state is
+  /// found again as long as the root does not change.
+  pub fn work_root(mut self, root: impl Into<PathBuf>) -> Self {
+    self.work_root = Some(root.into());
+    self
+  }
+
+  /// Leave source paths matching `pattern`, in `.gitignore` syntax, out of
+  /// the work directory.
+  ///
+  /// Adds to the `.terraformignore` and `.atarignore` of the sources. With
+  /// no `.terraformignore`, `.git/`, `.terraform/`, `*.tfstate` and
+  /// `*.tfstate.backup` are left out too.
+  pub fn ignore(mut self, pattern: impl Into<String>) -> Self {
+    self.ignore_patterns.push(pattern.into());
+    self
+  }
+
+  /// Share downloaded providers between work directories through the
+  /// plugin cache in `dir`, created if missing.
+  ///
+  /// Takes precedence over the `TF_PLUGIN_CACHE_DIR` environment variable,
+  /// which is otherwise honored the same way. Without either, every work
+  /// directory downloads its own providers. A relative `dir` is resolved
+  /// against the current directory.
+  pub fn plugin_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+    let dir = dir.into();
+    self.plugin_cache_dir = Some(std::path::absolute(&dir).unwrap_or(dir));
+    self
+  }
+
+  /// Keep Terraform's data directory, normally `.terraform` in the work
+  /// directory, at `dir` instead, passed as `TF_DATA_DIR`.
+  ///
+  /// Without this, `TF_DATA_DIR` is pinned to the work directory's
+  /// `.terraform`, so a value inherited from the environment cannot make
+  /// concurrent runs share one. A relative `dir` is resolved against the
+  /// current directory.
+  pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+    let dir = dir.into();
+    self.data_dir = Some(std::path::absolute(&dir).unwrap_or(dir));
+    self
+  }
+
+  /// Set Terraform's internal log level, passed as `TF_LOG` to every
+  /// Terraform command without touching this process' environment.
+  ///
+  /// Terraform logs to stderr unless [`DeployOptions::tf_log_path`] is
+  /// set too.
+  pub fn tf_log_level(mut self, level: TfLogLevel) -> Self {
+    self.tf_log_level = Some(level);
+    self
+  }
+
+  /// Have Terraform append its internal log to `path`, passed as
+  /// `TF_LOG_PATH`.
+  ///
+  /// Terraform only logs when a level is set, either with
+  /// [`DeployOptions::tf_log_level`] or an inherited `TF_LOG`. A relative
+  /// `path` is resolved against the current directory.
+  pub fn tf_log_path(mut self, path: impl Into<PathBuf>) -> Self {
+    let path = path.into();
+    self.tf_log_path = Some(std::path::absolute(&path).unwrap_or(path));
+    self
+  }
+
+  /// Once a deploy succeeds, write its outputs to `path` as a JSON object
+  /// mapping each name to its value.
+  ///
+  /// Sensitive values are written unmasked. The file is replaced
+  /// atomically and left untouched when the deploy fails.
+  pub fn output_file(mut self, path: impl Into<PathBuf>) -> Self {
+    self.output_file = Some(path.into());
+    self
+  }
+
+  /// Run `terraform validate` between init and apply when deploying, so
+  /// an invalid configuration fails with [`crate::Error::Validation`]
+  /// before anything is created.
+  pub fn validate(mut self, validate: bool) -> Self {
+    self.validate = validate;
+    self
+  }
+
+  /// Deploy with `terraform apply -refresh-only`, updating the state to
+  /// match real infrastructure without changing any of it, then read the
+  /// outputs as usual.
+  ///
+  /// Cannot be combined with [`DeployOptions::replace`].
+  pub fn refresh_only(mut self, refresh_only: bool) -> Self {
+    self.refresh_only = refresh_only;
+    self
+  }
+
+  /// Remove the work directory, state included, once `undeploy` has
+  /// destroyed every resource.
+  ///
+  /// Nothing is removed if destroy fails, so the state can be recovered.
+  pub fn cleanup(mut self, cleanup: bool) -> Self {
+    self.cleanup = cleanup;
+    self
+  }
+
+  /// Wait up to `timeout` for another process using the same work
+  /// directory to finish.
+  ///
+  /// By default the lock is tried once. Failing to get it is reported as
+  /// [`crate::Error::WorkspaceLocked`].
+  pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+    self.lock_timeout = timeout;
+    self
+  }
+
+  /// Report each step of `deploy` to `callback`, e.g. to drive a progress
+  /// bar.
+  ///
+  /// Lifecycle events are always reported; when apply output is shown, or
+  /// an output sink is set, every line of `terraform apply` output is
+  /// reported as well.
+  pub fn progress(
+    mut self,
+    callback: impl Fn(ProgressEvent) + Send + Sync + 'static,
+  ) -> Self {
+    self.progress = Some(Callback(Arc::new(callback)));
+    self
+  }
+
+  /// Send every line Terraform writes to stdout or stderr during init,
+  /// apply and destroy to `sink` as it arrives, instead of the terminal.
+  pub fn output_sink(
+    mut self,
+    sink: impl Fn(&str) + Send + Sync + 'static,
+  ) -> Self {
+    self.output_sink = Some(Callback(Arc::new(sink)));
+    self
+  }
+
+  /// Run `hook` with the work directory before a deploy runs any
+  /// Terraform command on it, e.g. to fetch credentials.
+  ///
+  /// If the hook fails, the deploy stops with [`Error::HookFailed`] and
+  /// Terraform is not run.
+  pub fn pre_deploy(
+    mut self,
+    hook: impl Fn(&Path) -> Result<(), HookError> + Send + Sync + 'static,
+  ) -> Self {
+    self.pre_deploy = Some(Callback(Arc::new(hook)));
+    self
+  }
+
+  /// Run `hook` with the stringified outputs once a deploy has read them,
+  /// e.g. to write a kubeconfig.
+  ///
+  /// If the hook fails, the deploy returns [`Error::HookFailed`]; the
+  /// resources it created are left in place.
+  pub fn post_deploy(
+    mut self,
+    hook: impl Fn(&HashMap<String, String>) -> Result<(), HookError>
+      + Send
+      + Sync
+      + 'static,
+  ) -> Self {
+    self.post_deploy = Some(Callback(Arc::new(hook)));
+    self
+  }
+
+  /// Run `hook` with the work directory before `undeploy` runs any
+  /// Terraform command on it.
+  ///
+  /// If the hook fails, nothing is destroyed and [`Error::HookFailed`] is
+  /// returned.
+  pub fn pre_destroy(
+    mut self,
+    hook: impl Fn(&Path) -> Result<(), HookError> + Send + Sync + 'static,
+  ) -> Self {
+    self.pre_destroy = Some(Callback(Arc::new(hook)));
+    self
+  }
+
+  /// Run `hook` with the work directory once `undeploy` has destroyed
+  /// every resource, before the work directory is cleaned up.
+  pub fn post_destroy(
+    mut self,
+    hook: impl Fn(&Path) -> Result<(), HookError> + Send + Sync + 'static,
+  ) -> Self {
+    self.post_destroy = Some(Callback(Arc::new(hook)));
+    self
+  }
+
+  /// Reject settings Terraform would fail on, before running anything.
+  pub(crate) fn check(&self) -> Result<(), Error> {
+    if let Some(parallelism) = self.parallelism {
+      if !(1..=MAX_PARALLELISM).contains(&parallelism) {
+        return Err(Error::InvalidOptions(format!(
+          "Parallelism must be between 1 and {}, got {}",
+          MAX_PARALLELISM, parallelism
+        )));
+      }
+    }
+    if self.retry.max_attempts == 0 {
+      return Err(Error::InvalidOptions(
+        "Retry policy must allow at least one attempt".to_string(),
+      ));
+    }
+    self.retry.matcher()?;
+    if self.destroy_retry.max_attempts == 0 {
+      return Err(Error::InvalidOptions(
+        "Destroy retry policy must allow at least one attempt".to_string(),
+      ));
+    }
+    self.destroy_retry.matcher()?;
+    if self.refresh_only && !self.replace_resources.is_empty() {
+      return Err(Error::MutuallyExclusiveFlags {
+        first: "refresh_only",
+        second: "replace",
+      });
+    }
+    if self.init_reconfigure && self.init_migrate_state {
+      return Err(Error::MutuallyExclusiveFlags {
+        first: "init_reconfigure",
+        second: "init_migrate_state",
+      });
+    }
+    if self.env_var_prefix.as_deref() == Some("") {
+      return Err(Error::InvalidOptions(
+        "Environment variable prefix must not be empty".to_string(),
+      ));
+    }
+    if let Some(key) =
+      self.vars.keys().find(|k| k.is_empty() || k.contains('='))
+    {
+      return Err(Error::InvalidOptions(format!(
+        "Invalid variable name `{}`: must be non-empty and contain no `=`",
+        key
+      )));
+    }
+    Ok(())
+  }
+
+  /// Report `event` to the progress callback, if any.
+  pub(crate) fn emit(&self, event: ProgressEvent) {
+    if let Some(progress) = &self.progress {
+      (progress.0)(event);
+    }
+  }
+
+  /// Directory work directories are created in.
+  pub(crate) fn resolved_work_root(&self) -> PathBuf {
+    self
+      .work_root
+      .clone()
+      .or_else(|| env::var_os(WORK_ROOT_ENV).map(PathBuf::from))
+      .unwrap_or_else(|| env::temp_dir().join("atar"))
+  }
+
+  /// Shared provider cache directory, if any.
+  pub(crate) fn resolved_plugin_cache_dir(&self) -> Option<PathBuf> {
+    self.plugin_cache_dir.clone().or_else(|| {
+      env::var_os(PLUGIN_CACHE_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+    })
+  }
+
+  /// Binary every Terraform command is spawned with.
+  pub(crate) fn program(&self) -> &Path {
+    self
+      .terraform_bin
+      .as_deref()
+      .unwrap_or(Path::new(Engine::Terraform.binary_name()))
+  }
+
+  /// Terraform data directory used for the work directory `work_dir`.
+  pub(crate) fn resolved_data_dir(&self, work_dir: &Path) -> PathBuf {
+    self
+      .data_dir
+      .clone()
+      .unwrap_or_else(|| work_dir.join(".terraform"))
+  }
+
+  /// A command running [`DeployOptions::program`] in `work_dir`, with
+  /// `TF_VAR_*` variables removed from its environment unless they are
+  /// inherited, and those from the env var prefix and env files added.
+  pub(crate) fn command(&self, work_dir: &Path) -> Command {
+    let mut cmd = Command::new(self.program());
+    cmd
+      .current_dir(work_dir)
+      .env(TF_DATA_DIR_ENV, self.resolved_data_dir(work_dir));
+    if let Some(dir) = &self.plugin_cache_dir {
+      cmd.env(PLUGIN_CACHE_ENV, dir);
+    }
+    if let Some(level) = self.tf_log_level {
+      cmd.env(TF_LOG_ENV, level.as_str());
+    }
+    if let Some(path) = &self.tf_log_path {
+      cmd.env(TF_LOG_PATH_ENV, path);
+    }
+    if self.skip_tf_vars {
+      for (key, _) in env::vars_os() {
+        if key.to_string_lossy().starts_with(TF_VAR_PREFIX) {
+          cmd.env_remove(key);
+        }
+      }
+    }
+    if let Some(prefix) = &self.env_var_prefix {
+      for (key, value) in env::vars_os() {
+        let name = key.to_str().and_then(|key| key.strip_prefix(prefix));
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+          cmd.env(format!("{}{}", TF_VAR_PREFIX, name), value);
+        }
+      }
+    }
+    for (name, value) in &self.env_file_vars {
+      cmd.env(format!("{}{}", TF_VAR_PREFIX, name), value);
+    }
+    cmd
+  }
+
+  /// Whether Terraform sees the `TF_VAR_*` variables of this process'
+  /// environment, as it does by default.
+  ///
+  /// Inherited variables stay out of the command line, so secrets set this
+  /// way do not show up in `ps`. Variables set with [`DeployOptions::var`]
+  /// take precedence over them.
+  pub fn inherit_tf_vars(mut self, inherit: bool) -> Self {
+    self.skip_tf_vars = !inherit;
+    self
+  }
+
+  /// Read Terraform variables from the environment variables starting with
+  /// `prefix`, e.g. `CI_SECRET_` turns `CI_SECRET_token` into `token`.
+  ///
+  /// The environment is read each time Terraform runs, and the variables
+  /// are handed over as `TF_VAR_*` environment variables rather than
+  /// arguments. They have the lowest priority, so variables set with
+  /// [`DeployOptions::var`] or in var-files win.
+  pub fn env_var_prefix(mut self, prefix: impl Into<String>) -> Self {
+    self.env_var_prefix = Some(prefix.into());
+    self
+  }
+
+  /// Pass variables to Terraform in a temporary `.tfvars.json` file instead
+  /// of `-var` flags, so their values do not show up in `ps`.
+  ///
+  /// The file is only readable by the current user and is removed once the
+  /// operation finishes. Values are written as JSON strings, so unlike with
+  /// `-var`, list, map and object variables fail Terraform's type checks;
+  /// set those in a var-file instead.
+  pub fn vars_via_file(mut self, via_file: bool) -> Self {
+    self.vars_via_file = via_file;
+    self
+  }
+
+  /// Set a Terraform variable, passed as `-var key=value`.
+  pub fn var(
+    mut self,
+    key: impl Into<String>,
+    value: impl Into<String>,
+  ) -> Self {
+    self.vars.insert(key.into(), value.into());
+    self
+  }
+
+  /// Set several Terraform variables at once.
+  pub fn vars<I, K, V>(mut self, vars: I) -> Self
+  where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+  {
+    self
+      .vars
+      .extend(vars.into_iter().map(|(k, v)| (k.into(), v.into())));
+    self
+  }
+
+  /// The Terraform variables set so far.
+  pub fn variables(&self) -> &HashMap<String, String> {
+    &self.vars
+  }
+
+  /// Add a `.tfvars` file, passed as `-var-file=<path>`.
+  ///
+  /// Relative paths are resolved against the current directory. The file is
+  /// copied into the work directory before each run, so paths inside it
+  /// resolve like those in the module itself. Values from var-files are
+  /// overridden by variables set with [`DeployOptions::var`].
+  pub fn var_file(mut self, path: impl Into<PathBuf>) -> Self {
+    self.var_files.push(path.into());
+    self
+  }
+
+  /// Add a `.env` file of `KEY=VALUE` lines to read Terraform variables
+  /// from.
+  ///
+  /// Blank lines and `#` comments are skipped, and values may be quoted;
+  /// nothing is expanded. Keys that are not valid variable names are
+  /// ignored. Like [`DeployOptions::env_var_prefix`], the values are handed
+  /// over as `TF_VAR_*` environment variables with the lowest priority;
+  /// among env files, later ones win.
+  pub fn env_file(mut self, path: impl Into<PathBuf>) -> Self {
+    self.env_files.push(path.into());
+    self
+  }
+
+  /// Restrict apply, plan and destroy to `resource`, passed as
+  /// `-target=<resource>`.
+  ///
+  /// May be called repeatedly to target several resources.
+  pub fn target(mut self, resource: impl Into<String>) -> Self {
+    self.targets.push(resource.into());
+    self
+  }
+
+  /// Append raw arguments to `terraform apply` and `terraform destroy`,
+  /// for flags without an option of their own.
+  ///
+  /// They come after every argument atar adds, so they can override those,
+  /// e.g. `-parallelism=30`. May be called repeatedly.
+  pub fn extra_args<I, S>(mut self, args: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    self.extra_args.extend(args.into_iter().map(Into::into));
+    self
+  }
+
+  /// Set a backend setting for `terraform init`, passed as
+  /// `-backend-config=key=value`, e.g. the bucket of an S3 backend.
+  ///
+  /// May be called repeatedly. Only init receives these settings.
+  pub fn backend_config(
+    mut self,
+    key: impl Into<String>,
+    value: impl Into<String>,
+  ) -> Self {
+    self.backend_config.push((key.into(), value.into()));
+    self
+  }
+
+  /// Add a file of backend settings for `terraform init`, passed as
+  /// `-backend-config=<path>`.
+  ///
+  /// Relative paths are resolved against the current directory. Settings
+  /// from [`DeployOptions::backend_config`] override those from files.
+  pub fn backend_config_file(mut self, path: impl Into<PathBuf>) -> Self {
+    let path = path.into();
+    self
+      .backend_config_files
+      .push(std::path::absolute(&path).unwrap_or(path));
+    self
+  }
+
+  /// Pass `-upgrade` to `terraform init`, updating providers and modules to
+  /// the newest versions the configuration allows instead of those in the
+  /// lock file.
+  ///
+  /// Init then runs even in a work directory that was initialized before.
+  pub fn init_upgrade(mut self, upgrade: bool) -> Self {
+    self.init_upgrade = upgrade;
+    self
+  }
+
+  /// Pass `-reconfigure` to `terraform init`, switching to a changed
+  /// backend configuration without migrating the existing state.
+  ///
+  /// Cannot be combined with [`DeployOptions::init_migrate_state`].
+  pub fn init_reconfigure(mut self, reconfigure: bool) -> Self {
+    self.init_reconfigure = reconfigure;
+    self
+  }
+
+  /// Pass `-migrate-state` to `terraform init`, copying the existing state
+  /// to a changed backend configuration.
+  ///
+  /// Cannot be combined with [`DeployOptions::init_reconfigure`].
+  pub fn init_migrate_state(mut self, migrate: bool) -> Self {
+    self.init_migrate_state = migrate;
+    self
+  }
+
+  /// Run in the Terraform workspace `name`, created if missing.
+  ///
+  /// Selected with `terraform workspace select -or-create` before plan,
+  /// apply and destroy, and used when reading outputs.
+  pub fn workspace(mut self, name: impl Into<String>) -> Self {
+    self.workspace = Some(name.into());
+    self
+  }
+
+  /// Force `resource` to be destroyed and recreated on apply, passed as
+  /// `-replace=<resource>`.
+  ///
+  /// May be called repeatedly. Ignored by destroy.
+  pub fn replace(mut self, resource: impl Into<String>) -> Self {
+    self.replace_resources.push(resource.into());
+    self
+  }
+
+  /// Show Terraform's own output instead of silencing it.
+  ///
+  /// Shorthand for `.log(LogConfig::from(debug))`, replacing any earlier
+  /// [`DeployOptions::log`] setting.
+  pub fn debug(mut self, debug: bool) -> Self {
+    self.log = LogConfig::from(debug);
+    self
+  }
+
+  /// Choose per step whether Terraform's output is shown, and how much
+  /// atar prints itself.
+  pub fn log(mut self, log: LogConfig) -> Self {
+    self.log = log;
+    self
+  }
+
+  /// Limit concurrent resource operations, passed as `-parallelism=<n>`.
+  ///
+  /// Must be between 1 and 512; other values are rejected when the options
+  /// are used. Without this, Terraform's default of 10 applies.
+  pub fn parallelism(mut self, parallelism: u32) -> Self {
+    self.parallelism = Some(parallelism);
+    self
+  }
+
+  /// Whether Terraform locks the state while it runs, passed as
+  /// `-lock=<lock>`.
+  ///
+  /// Without this, Terraform locks the state if its backend supports it.
+  /// Turning locking off is only safe when nothing else can touch the
+  /// state, e.g. local state during development. Unrelated to
+  /// [`DeployOptions::lock_timeout`], which is about the work directory.
+  pub fn lock(mut self, lock: bool) -> Self {
+    self.state_lock = Some(lock);
+    self
+  }
+
+  /// Abort the whole operation, killing Terraform, once `timeout` elapses.
+  ///
+  /// Expiry is reported as [`crate::Error::Timeout`].
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// Kill `terraform init`, along with any provider plugins it started, if
+  /// it runs longer than `timeout`.
+  ///
+  /// Expiry is reported as [`crate::Error::Timeout`].
+  pub fn init_timeout(mut self, timeout: Duration) -> Self {
+    self.init_timeout = Some(timeout);
+    self
+  }
+
+  /// Kill `terraform apply`, along with any provider plugins it started, if
+  /// it runs longer than `timeout`.
+  ///
+  /// Expiry is reported as [`crate::Error::Timeout`].
+  pub fn apply_timeout(mut self, timeout: Duration) -> Self {
+    self.apply_timeout = Some(timeout);
+    self
+  }
+
+  /// Retry `terraform apply` after transient failures as described by
+  /// `policy`.
+  ///
+  /// By default a failed apply is not retried.
+  pub fn retry(mut self, policy: RetryPolicy) -> Self {
+    self.retry = policy;
+    self
+  }
+
+  /// Retry a failed `terraform apply` up to `retries` times, whatever the
+  /// error, instead of only after transient failures.
+  ///
+  /// Shorthand for a [`RetryPolicy`] with no transient patterns, keeping
+  /// the backoff already set.
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.retry.max_attempts = retries.saturating_add(1);
+    self.retry.transient_patterns.clear();
+    self
+  }
+
+  /// Wait `backoff` before the first apply retry, then twice as long after
+  /// each further failure, up to the cap already set, a minute by default.
+  pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+    let max = match self.retry.backoff {
+      BackoffStrategy::Exponential { max, .. } => max,
+      BackoffStrategy::Fixed(_) => DEFAULT_MAX_BACKOFF,
+    };
+    self.retry.backoff = BackoffStrategy::Exponential {
+      base: backoff,
+      max: max.max(backoff),
+    };
+    self
+  }
+
+  /// Retry `terraform destroy` after transient failures as described by
+  /// `policy`, so cloud rate limits do not leave resources behind.
+  ///
+  /// By default a failed destroy is not retried.
+  pub fn destroy_retry(mut self, policy: RetryPolicy) -> Self {
+    self.destroy_retry = policy;
+    self
+  }
+
+  /// Kill `terraform destroy`, along with any provider plugins it started,
+  /// if it runs longer than `timeout`.
+  ///
+  /// Expiry is reported as [`crate::Error::Timeout`].
+  pub fn destroy_timeout(mut self, timeout: Duration) -> Self {
+    self.destroy_timeout = Some(timeout);
+    self
+  }
+}
@@ -0,0 +1,177 @@
+//! Terraform outputs as reported by `terraform output -json`.
+
+use crate::error::{Error, Result};
+use serde_json::{Map, Value};
+use std::{
+  collections::HashMap,
+  fmt,
+  fs::{self, OpenOptions},
+  io::{self, Write},
+  path::Path,
+};
+
+/// A single Terraform output as reported by `terraform output -json`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerraformOutput {
+  /// The output value, preserved as JSON.
+  pub value: Value,
+  /// Whether the output is marked `sensitive` in the configuration.
+  pub sensitive: bool,
+  /// The Terraform type constraint of the output, as JSON.
+  pub type_: Value,
+}
+
+/// Displays the value, masking it as `<sensitive>` when needed.
+impl fmt::Display for TerraformOutput {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.sensitive {
+      write!(f, "<sensitive>")
+    } else {
+      write!(f, "{}", value_to_string(&self.value))
+    }
+  }
+}
+
+/// A Terraform output value converted to a Rust type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputValue {
+  /// A `string` output.
+  Str(String),
+  /// A `number` output.
+  Number(f64),
+  /// A `bool` output.
+  Bool(bool),
+  /// A `list`, `set` or `tuple` output.
+  List(Vec<OutputValue>),
+  /// A `map` or `object` output.
+  Map(HashMap<String, OutputValue>),
+  /// A `null` output.
+  Null,
+}
+
+impl From<Value> for OutputValue {
+  fn from(value: Value) -> Self {
+    match value {
+      Value::Null => OutputValue::Null,
+      Value::Bool(b) => OutputValue::Bool(b),
+      // Every JSON number is representable as `f64`, if lossily.
+      Value::Number(n) => OutputValue::Number(n.as_f64().unwrap_or(f64::NAN)),
+      Value::String(s) => OutputValue::Str(s),
+      Value::Array(items) => {
+        OutputValue::List(items.into_iter().map(Into::into).collect())
+      }
+      Value::Object(fields) => OutputValue::Map(
+        fields.into_iter().map(|(k, v)| (k, v.into())).collect(),
+      ),
+    }
+  }
+}
+
+/// A Terraform output with its value converted to an [`OutputValue`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedOutput {
+  /// The converted output value.
+  pub value: OutputValue,
+  /// Whether the output is marked `sensitive` in the configuration.
+  pub sensitive: bool,
+}
+
+impl From<TerraformOutput> for TypedOutput {
+  fn from(output: TerraformOutput) -> Self {
+    TypedOutput {
+      value: output.value.into(),
+      sensitive: output.sensitive,
+    }
+  }
+}
+
+/// Parse the stdout of `terraform output -json` into detailed outputs.
+pub(crate) fn parse_outputs(
+  stdout: &[u8],
+) -> Result<HashMap<String, TerraformOutput>> {
+  let raw: HashMap<String, Value> = serde_json::from_slice(stdout)?;
+  let mut results = HashMap::new();
+  for (key, mut val) in raw {
+    let Some(value) = val.get_mut("value").map(Value::take) else {
+      continue;
+    };
+    let sensitive = val
+      .get("sensitive")
+      .and_then(Value::as_bool)
+      .unwrap_or(false);
+    let type_ = val.get_mut("type").map(Value::take).unwrap_or_default();
+    results.insert(
+      key,
+      TerraformOutput {
+        value,
+        sensitive,
+        type_,
+      },
+    );
+  }
+  Ok(results)
+}
+
+/// Render a JSON value as a string, leaving string values unquoted.
+pub(crate) fn value_to_string(value: &Value) -> String {
+  match value {
+    Value::String(s) => s.clone(),
+    other => other.to_string(),
+  }
+}
+
+/// Convert each detailed output into a [`TypedOutput`].
+pub(crate) fn typed_outputs(
+  outputs: HashMap<String, TerraformOutput>,
+) -> HashMap<String, TypedOutput> {
+  outputs
+    .into_iter()
+    .map(|(key, output)| (key, output.into()))
+    .collect()
+}
+
+/// Write `outputs` to `path` as a JSON object of raw values, sensitive ones
+/// included.
+///
+/// The file is written next to `path` first and renamed into place, so
+/// readers never see it half-written. On unix only the owner can read it.
+pub(crate) fn write_outputs_file(
+  path: &Path,
+  outputs: &HashMap<String, TerraformOutput>,
+) -> Result<()> {
+  let values: Map<String, Value> = outputs
+    .iter()
+    .map(|(key, output)| (key.clone(), output.value.clone()))
+    .collect();
+  let mut tmp_name = path.as_os_str().to_owned();
+  tmp_name.push(format!(".{}.tmp", std::process::id()));
+  let tmp = Path::new(&tmp_name);
+  let write = || -> io::Result<()> {
+    let mut open = OpenOptions::new();
+    open.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut open, 0o600);
+    let mut file = open.open(tmp)?;
+    serde_json::to_writer_pretty(&mut file, &values)?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+    fs::rename(tmp, path)
+  };
+  write().map_err(|source| {
+    let _ = fs::remove_file(tmp);
+    Error::OutputFileFailed {
+      path: path.to_path_buf(),
+      source,
+    }
+  })
+}
+
+/// Render each detailed output value as a string.
+pub(crate) fn stringify_outputs(
+  outputs: HashMap<String, TerraformOutput>,
+) -> HashMap<String, String> {
+  outputs
+    .into_iter()
+    .map(|(key, output)| (key, value_to_string(&output.value)))
+    .collect()
+}
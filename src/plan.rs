@@ -0,0 +1,396 @@
+//! Parsing of `terraform plan -json` and `terraform apply -json` output.
+
+use crate::error::Result;
+use serde_json::Value;
+use std::{io::BufRead, path::PathBuf};
+
+/// Summary of the changes a saved Terraform plan would make.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanSummary {
+  /// Number of resources the plan would create.
+  pub to_add: u32,
+  /// Number of resources the plan would update in place.
+  pub to_change: u32,
+  /// Number of resources the plan would destroy.
+  pub to_destroy: u32,
+  /// Every machine-readable message emitted by `terraform plan -json`, in
+  /// order, as a JSON array.
+  pub raw_plan_json: Value,
+  /// Work directory Terraform planned in.
+  pub work_dir: PathBuf,
+  /// Saved plan file, ready for `deploy_from_plan`.
+  pub plan_file: PathBuf,
+}
+
+/// Resources a `terraform apply` added, changed and destroyed, as reported
+/// in its `change_summary` message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChangeCounts {
+  /// Number of resources created.
+  pub added: u32,
+  /// Number of resources updated in place.
+  pub changed: u32,
+  /// Number of resources destroyed.
+  pub destroyed: u32,
+}
+
+impl ChangeCounts {
+  /// Whether nothing was added, changed or destroyed.
+  pub fn is_empty(&self) -> bool {
+    self.added == 0 && self.changed == 0 && self.destroyed == 0
+  }
+}
+
+/// The counts of `message` if it is a `change_summary` message of the
+/// machine-readable UI.
+pub(crate) fn change_counts(message: &Value) -> Option<ChangeCounts> {
+  if message.get("type").and_then(Value::as_str) != Some("change_summary") {
+    return None;
+  }
+  let changes = &message["changes"];
+  let count =
+    |key: &str| changes.get(key).and_then(Value::as_u64).unwrap_or(0) as u32;
+  Some(ChangeCounts {
+    added: count("add"),
+    changed: count("change"),
+    destroyed: count("remove"),
+  })
+}
+
+/// Counts and messages extracted from a `terraform plan -json` stream.
+pub(crate) struct ParsedPlan {
+  pub(crate) to_add: u32,
+  pub(crate) to_change: u32,
+  pub(crate) to_destroy: u32,
+  pub(crate) messages: Vec<Value>,
+}
+
+/// Parse the JSON-lines stream written by `terraform plan -json`.
+///
+/// Change counts are taken from the `change_summary` message.
+pub(crate) fn parse_plan_stream(stdout: &[u8]) -> Result<ParsedPlan> {
+  let mut parsed = ParsedPlan {
+    to_add: 0,
+    to_change: 0,
+    to_destroy: 0,
+    messages: Vec::new(),
+  };
+  let text = String::from_utf8_lossy(stdout);
+  for line in text.lines().filter(|l| !l.trim().is_empty()) {
+    let message: Value = serde_json::from_str(line)?;
+    if let Some(counts) = change_counts(&message) {
+      parsed.to_add = counts.added;
+      parsed.to_change = counts.changed;
+      parsed.to_destroy = counts.destroyed;
+    }
+    parsed.messages.push(message);
+  }
+  Ok(parsed)
+}
+
+/// What a plan would do to a resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Action {
+  /// The resource would be created.
+  Create,
+  /// The resource would be updated in place.
+  Update,
+  /// The resource would be destroyed.
+  Delete,
+  /// The resource would be destroyed and created again.
+  Replace,
+  /// The resource would be left as is.
+  NoOp,
+}
+
+impl Action {
+  /// Map an action as named in `planned_change` messages.
+  fn from_name(name: &str) -> Option<Action> {
+    match name {
+      "create" => Some(Action::Create),
+      "update" => Some(Action::Update),
+      "delete" => Some(Action::Delete),
+      "replace" => Some(Action::Replace),
+      "noop" | "no-op" => Some(Action::NoOp),
+      _ => None,
+    }
+  }
+
+  /// Map the `actions` list of a `resource_changes` entry.
+  fn from_names(names: &[&str]) -> Option<Action> {
+    match names {
+      [name] => Action::from_name(name),
+      ["delete", "create"] | ["create", "delete"] => Some(Action::Replace),
+      _ => None,
+    }
+  }
+}
+
+/// A planned change to a single resource.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceChange {
+  /// Resource address, e.g. `aws_instance.web`.
+  pub address: String,
+  /// What would happen to the resource.
+  pub action: Action,
+  /// Resource attributes before the change, when known.
+  pub before: Option<Value>,
+  /// Resource attributes after the change, when known.
+  pub after: Option<Value>,
+}
+
+/// Every resource change of a Terraform plan.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeSet {
+  /// Changes in the order Terraform reported them.
+  pub changes: Vec<ResourceChange>,
+}
+
+impl ChangeSet {
+  /// Changes with the given `action`.
+  pub fn with_action(
+    &self,
+    action: Action,
+  ) -> impl Iterator<Item = &ResourceChange> {
+    self.changes.iter().filter(move |c| c.action == action)
+  }
+
+  /// Whether any resource would be destroyed, replacements included.
+  pub fn has_deletions(&self) -> bool {
+    self
+      .changes
+      .iter()
+      .any(|c| matches!(c.action, Action::Delete | Action::Replace))
+  }
+}
+
+/// Parse Terraform's machine-readable plan into a [`ChangeSet`].
+///
+/// Accepts the JSON-lines stream of `terraform plan -json`, whose
+/// `planned_change` messages carry no attribute values, so `before` and
+/// `after` are `None`. The JSON document printed by
+/// `terraform show -json <plan>` is accepted too, pretty-printed or not, and
+/// fills them in.
+/// Actions other than create, update, delete, replace and no-op, such as
+/// data source reads, are skipped.
+pub fn parse_plan_json(reader: impl BufRead) -> Result<ChangeSet> {
+  let mut set = ChangeSet::default();
+  for message in serde_json::Deserializer::from_reader(reader).into_iter() {
+    let message: Value = message?;
+    if let Some(changes) = message.get("resource_changes") {
+      set.changes.extend(
+        changes
+          .as_array()
+          .into_iter()
+          .flatten()
+          .filter_map(shown_change),
+      );
+    } else if message.get("type").and_then(Value::as_str)
+      == Some("planned_change")
+    {
+      set.changes.extend(streamed_change(&message));
+    }
+  }
+  Ok(set)
+}
+
+/// Changes made to resources outside of Terraform, as found by refreshing
+/// the state during a plan.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DriftReport {
+  /// Whether any resource differs from the state.
+  pub has_drift: bool,
+  /// The drifted resources, with what happened to each of them.
+  pub changes: Vec<ResourceChange>,
+}
+
+/// Collect the `resource_drift` messages of a `terraform plan -json`
+/// stream into a [`DriftReport`].
+///
+/// Changes the plan would make because the configuration changed are not
+/// drift and are left out.
+pub(crate) fn parse_drift_stream(messages: &[Value]) -> DriftReport {
+  let changes: Vec<ResourceChange> = messages
+    .iter()
+    .filter(|message| {
+      message.get("type").and_then(Value::as_str) == Some("resource_drift")
+    })
+    .filter_map(streamed_change)
+    .collect();
+  DriftReport {
+    has_drift: !changes.is_empty(),
+    changes,
+  }
+}
+
+/// Read a `planned_change` or `resource_drift` message of
+/// `terraform plan -json`.
+fn streamed_change(message: &Value) -> Option<ResourceChange> {
+  let change = message.get("change")?;
+  Some(ResourceChange {
+    address: change.pointer("/resource/addr")?.as_str()?.to_string(),
+    action: Action::from_name(change.get("action")?.as_str()?)?,
+    before: None,
+    after: None,
+  })
+}
+
+/// Read a `resource_changes` entry of `terraform show -json`.
+fn shown_change(entry: &Value) -> Option<ResourceChange> {
+  let change = entry.get("change")?;
+  let actions: Vec<&str> = change
+    .get("actions")?
+    .as_array()?
+    .iter()
+    .filter_map(Value::as_str)
+    .collect();
+  let value = |key: &str| change.get(key).filter(|v| !v.is_null()).cloned();
+  Some(ResourceChange {
+    address: entry.get("address")?.as_str()?.to_string(),
+    action: Action::from_names(&actions)?,
+    before: value("before"),
+    after: value("after"),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn planned(kind: &str, addr: &str, action: &str) -> String {
+    let change = json!({ "resource": { "addr": addr }, "action": action });
+    json!({ "type": kind, "change": change }).to_string()
+  }
+
+  fn streamed(address: &str, action: Action) -> ResourceChange {
+    ResourceChange {
+      address: address.to_string(),
+      action,
+      before: None,
+      after: None,
+    }
+  }
+
+  #[test]
+  fn parses_planned_changes_of_a_json_lines_stream() {
+    let stream = [
+      json!({ "type": "version", "terraform": "1.9.0" }).to_string(),
+      planned("planned_change", "a.one", "create"),
+      String::new(),
+      planned("planned_change", "a.two", "update"),
+      planned("resource_drift", "a.drifted", "update"),
+      planned("planned_change", "a.three", "delete"),
+      planned("planned_change", "a.four", "replace"),
+      planned("planned_change", "a.five", "no-op"),
+      planned("planned_change", "data.a.six", "read"),
+    ]
+    .join("\n");
+    let set = parse_plan_json(stream.as_bytes()).unwrap();
+    let expected = [
+      streamed("a.one", Action::Create),
+      streamed("a.two", Action::Update),
+      streamed("a.three", Action::Delete),
+      streamed("a.four", Action::Replace),
+      streamed("a.five", Action::NoOp),
+    ];
+    assert_eq!(set.changes, expected);
+    assert!(set.has_deletions());
+    assert_eq!(set.with_action(Action::Update).count(), 1);
+  }
+
+  #[test]
+  fn parses_resource_changes_of_show_json() {
+    let show = json!({
+      "format_version": "1.2",
+      "resource_changes": [
+        {
+          "address": "a.new",
+          "change": {
+            "actions": ["create"],
+            "before": null,
+            "after": { "x": 1 }
+          }
+        },
+        {
+          "address": "a.swapped",
+          "change": { "actions": ["create", "delete"], "before": { "x": 1 } }
+        },
+        {
+          "address": "a.rebuilt",
+          "change": { "actions": ["delete", "create"] }
+        },
+        {
+          "address": "data.a.read",
+          "change": { "actions": ["read"] }
+        }
+      ]
+    });
+    for document in [show.to_string(), format!("{:#}", show)] {
+      let set = parse_plan_json(document.as_bytes()).unwrap();
+      let actions: Vec<Action> = set.changes.iter().map(|c| c.action).collect();
+      assert_eq!(actions, [Action::Create, Action::Replace, Action::Replace]);
+      assert_eq!(set.changes[0].before, None);
+      assert_eq!(set.changes[0].after, Some(json!({ "x": 1 })));
+      assert_eq!(set.changes[1].before, Some(json!({ "x": 1 })));
+      assert_eq!(set.changes[1].after, None);
+    }
+  }
+
+  #[test]
+  fn rejects_invalid_json() {
+    assert!(parse_plan_json("{\"type\":".as_bytes()).is_err());
+    assert!(parse_plan_json("not json".as_bytes()).is_err());
+  }
+
+  #[test]
+  fn empty_plan_has_no_changes() {
+    let set = parse_plan_json("\n\n".as_bytes()).unwrap();
+    assert!(set.changes.is_empty());
+    assert!(!set.has_deletions());
+  }
+
+  #[test]
+  fn drift_report_keeps_only_resource_drift() {
+    let stream = [
+      planned("resource_drift", "a.edited", "update"),
+      planned("planned_change", "a.new", "create"),
+      planned("resource_drift", "a.gone", "delete"),
+    ]
+    .join("\n");
+    let parsed = parse_plan_stream(stream.as_bytes()).unwrap();
+    let report = parse_drift_stream(&parsed.messages);
+    assert!(report.has_drift);
+    let expected = [
+      streamed("a.edited", Action::Update),
+      streamed("a.gone", Action::Delete),
+    ];
+    assert_eq!(report.changes, expected);
+
+    let parsed =
+      parse_plan_stream(planned("planned_change", "a", "create").as_bytes());
+    assert_eq!(
+      parse_drift_stream(&parsed.unwrap().messages),
+      DriftReport::default()
+    );
+  }
+
+  #[test]
+  fn plan_stream_counts_come_from_the_change_summary() {
+    let summary = json!({
+      "type": "change_summary",
+      "changes": { "add": 2, "change": 1, "remove": 3, "operation": "plan" }
+    });
+    let stream = format!(
+      "{}\n\n{}\n",
+      planned("planned_change", "a", "create"),
+      summary
+    );
+    let parsed = parse_plan_stream(stream.as_bytes()).unwrap();
+    let counts = (parsed.to_add, parsed.to_change, parsed.to_destroy);
+    assert_eq!(counts, (2, 1, 3));
+    assert_eq!(parsed.messages.len(), 2);
+    assert_eq!(change_counts(&parsed.messages[0]), None);
+  }
+}
@@ -0,0 +1,337 @@
+//! Running Terraform processes with deadlines and line-by-line output.
+
+use crate::error::{Error, Result};
+use std::{
+  io::{self, BufRead, BufReader, Read},
+  process::{Child, Command, ExitStatus, Stdio},
+  sync::{Arc, Mutex},
+  thread::{self, JoinHandle},
+  time::{Duration, Instant},
+};
+
+/// How often a running Terraform process is checked against its deadline.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How much of a failed command's stderr is kept for its error message.
+const STDERR_TAIL_BYTES: usize = 4096;
+
+/// Receives each line a Terraform process writes, as it arrives.
+pub(crate) type LineHandler = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Handlers taking over a process' stdout and stderr.
+///
+/// Stdout without a handler keeps the command's own stdio configuration.
+/// Stderr is always captured for error messages, so without a handler it
+/// is not shown.
+#[derive(Clone, Default)]
+pub(crate) struct LineHandlers {
+  pub(crate) stdout: Option<LineHandler>,
+  pub(crate) stderr: Option<LineHandler>,
+}
+
+/// The last [`STDERR_TAIL_BYTES`] of the stderr lines seen so far.
+#[derive(Clone, Default)]
+struct StderrTail(Arc<Mutex<String>>);
+
+impl StderrTail {
+  /// Wrap `forward` so every line passed to it is also recorded.
+  fn recorder(&self, forward: Option<LineHandler>) -> LineHandler {
+    let tail = self.clone();
+    Arc::new(move |line: &str| {
+      if let Some(forward) = &forward {
+        forward(line);
+      }
+      tail.push(line);
+    })
+  }
+
+  fn push(&self, line: &str) {
+    let mut text = self.0.lock().unwrap_or_else(|e| e.into_inner());
+    text.push_str(line);
+    text.push('\n');
+    if text.len() > 2 * STDERR_TAIL_BYTES {
+      let cut = tail_start(&text);
+      text.drain(..cut);
+    }
+  }
+
+  fn take(&self) -> String {
+    let text = self.0.lock().unwrap_or_else(|e| e.into_inner());
+    text[tail_start(&text)..].to_string()
+  }
+}
+
+/// Byte offset where the last [`STDERR_TAIL_BYTES`] of `text` start, moved
+/// forward to a character boundary.
+fn tail_start(text: &str) -> usize {
+  let mut start = text.len().saturating_sub(STDERR_TAIL_BYTES);
+  while !text.is_char_boundary(start) {
+    start += 1;
+  }
+  start
+}
+
+/// Error for `name` exiting with `status`, carrying the tail of its
+/// stderr.
+///
+/// Init, apply and destroy get their own variants; other commands are
+/// reported as [`Error::CommandFailed`].
+pub(crate) fn command_failed(
+  name: &str,
+  status: ExitStatus,
+  stderr: &str,
+) -> Error {
+  let stderr = stderr[tail_start(stderr)..].trim_end().to_string();
+  let exit_code = status.code().unwrap_or(-1);
+  match name {
+    "terraform init" => Error::InitFailed { stderr, exit_code },
+    "terraform apply" => Error::ApplyFailed { stderr, exit_code },
+    "terraform destroy" => Error::DestroyFailed { stderr, exit_code },
+    _ => Error::CommandFailed {
+      command: name.to_string(),
+      stderr,
+      exit_code,
+    },
+  }
+}
+
+/// Wrap an I/O error raised while running `name`.
+fn io_error(name: &str) -> impl FnOnce(io::Error) -> Error + '_ {
+  move |source| Error::CommandIo {
+    command: name.to_string(),
+    source,
+  }
+}
+
+/// Error for a reader thread of `name` that panicked.
+fn reader_panicked(name: &str) -> Error {
+  io_error(name)(io::Error::other("output reader panicked"))
+}
+
+/// Put `cmd` in its own process group so that a timeout can kill everything
+/// it spawns, including provider plugins.
+fn isolate_process_group(cmd: &mut Command) {
+  #[cfg(unix)]
+  std::os::unix::process::CommandExt::process_group(cmd, 0);
+  #[cfg(not(unix))]
+  let _ = cmd;
+}
+
+/// Kill the process group led by the process `pid`.
+fn kill_process_group(pid: u32) {
+  #[cfg(unix)]
+  // SAFETY: `kill` has no memory-safety preconditions.
+  unsafe {
+    libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+  }
+  #[cfg(not(unix))]
+  let _ = pid;
+}
+
+/// The earlier of the operation-wide `deadline` and `timeout` from now.
+pub(crate) fn step_deadline(
+  deadline: Option<Instant>,
+  timeout: Option<Duration>,
+) -> Option<Instant> {
+  let step = timeout.map(|t| Instant::now() + t);
+  match (deadline, step) {
+    (Some(a), Some(b)) => Some(a.min(b)),
+    (a, b) => a.or(b),
+  }
+}
+
+/// Wait for `child` to exit, killing it if `deadline` passes first.
+fn wait_with_deadline(
+  child: &mut Child,
+  name: &str,
+  deadline: Option<Instant>,
+) -> Result<ExitStatus> {
+  let Some(deadline) = deadline else {
+    return child.wait().map_err(io_error(name));
+  };
+  let started = Instant::now();
+  loop {
+    if let Some(status) = child.try_wait().map_err(io_error(name))? {
+      return Ok(status);
+    }
+    if Instant::now() >= deadline {
+      kill_process_group(child.id());
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(Error::Timeout {
+        operation: name.to_string(),
+        elapsed: started.elapsed(),
+      });
+    }
+    thread::sleep(POLL_INTERVAL);
+  }
+}
+
+/// Feed every line read from `stream` to `on_line` on a background thread.
+fn spawn_line_reader(
+  stream: impl Read + Send + 'static,
+  on_line: LineHandler,
+) -> JoinHandle<std::io::Result<()>> {
+  thread::spawn(move || {
+    for line in BufReader::new(stream).lines() {
+      on_line(&line?);
+    }
+    Ok(())
+  })
+}
+
+/// Run `cmd` to completion and check its exit status.
+///
+/// Streams with a handler in `handlers` are piped and handed to it line by
+/// line while the process runs. On failure the error ends with the tail of
+/// stderr. When `deadline` is set and passes first, the process and
+/// everything it spawned are killed and an [`Error::Timeout`] is returned.
+pub(crate) fn run_command(
+  mut cmd: Command,
+  name: &str,
+  deadline: Option<Instant>,
+  handlers: LineHandlers,
+) -> Result<()> {
+  if deadline.is_some() {
+    isolate_process_group(&mut cmd);
+  }
+  if handlers.stdout.is_some() {
+    cmd.stdout(Stdio::piped());
+  }
+  cmd.stderr(Stdio::piped());
+  tracing::debug!("Running {:?}", cmd);
+  let mut child = cmd.spawn().map_err(io_error(name))?;
+  let tail = StderrTail::default();
+  let mut readers = Vec::new();
+  if let (Some(on_line), Some(stdout)) = (handlers.stdout, child.stdout.take())
+  {
+    readers.push(spawn_line_reader(stdout, on_line));
+  }
+  if let Some(stderr) = child.stderr.take() {
+    readers.push(spawn_line_reader(stderr, tail.recorder(handlers.stderr)));
+  }
+  let status = wait_with_deadline(&mut child, name, deadline)?;
+  for reader in readers {
+    reader
+      .join()
+      .map_err(|_| reader_panicked(name))?
+      .map_err(io_error(name))?;
+  }
+  if !status.success() {
+    return Err(command_failed(name, status, &tail.take()));
+  }
+  Ok(())
+}
+
+/// Like [`run_command`], but captures and returns the process stdout.
+pub(crate) fn run_command_capture(
+  mut cmd: Command,
+  name: &str,
+  deadline: Option<Instant>,
+  on_stderr: Option<LineHandler>,
+) -> Result<Vec<u8>> {
+  if deadline.is_some() {
+    isolate_process_group(&mut cmd);
+  }
+  tracing::debug!("Running {:?}", cmd);
+  let mut child = cmd
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(io_error(name))?;
+  let not_piped = || io_error(name)(io::Error::other("stream not piped"));
+  let mut stdout = child.stdout.take().ok_or_else(not_piped)?;
+  let reader = thread::spawn(move || {
+    let mut buf = Vec::new();
+    stdout.read_to_end(&mut buf).map(|_| buf)
+  });
+  let tail = StderrTail::default();
+  let stderr = child.stderr.take().ok_or_else(not_piped)?;
+  let stderr_reader = spawn_line_reader(stderr, tail.recorder(on_stderr));
+  let status = wait_with_deadline(&mut child, name, deadline)?;
+  let captured = reader
+    .join()
+    .map_err(|_| reader_panicked(name))?
+    .map_err(io_error(name))?;
+  stderr_reader
+    .join()
+    .map_err(|_| reader_panicked(name))?
+    .map_err(io_error(name))?;
+  if !status.success() {
+    return Err(command_failed(name, status, &tail.take()));
+  }
+  Ok(captured)
+}
+
+/// Feed every line read from `stream` to `on_line`, if both are present.
+#[cfg(feature = "async")]
+async fn read_lines_async(
+  stream: Option<impl tokio::io::AsyncRead + Unpin>,
+  on_line: Option<LineHandler>,
+) -> std::io::Result<()> {
+  use tokio::io::{AsyncBufReadExt, BufReader};
+
+  if let (Some(stream), Some(on_line)) = (stream, on_line) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines.next_line().await? {
+      on_line(&line);
+    }
+  }
+  Ok(())
+}
+
+/// Async counterpart of [`run_command`].
+#[cfg(feature = "async")]
+pub(crate) async fn run_command_async(
+  mut cmd: Command,
+  name: &str,
+  deadline: Option<Instant>,
+  handlers: LineHandlers,
+) -> Result<()> {
+  if deadline.is_some() {
+    isolate_process_group(&mut cmd);
+  }
+  if handlers.stdout.is_some() {
+    cmd.stdout(Stdio::piped());
+  }
+  cmd.stderr(Stdio::piped());
+  tracing::debug!("Running {:?}", cmd);
+  let started = Instant::now();
+  let mut child = tokio::process::Command::from(cmd)
+    .spawn()
+    .map_err(io_error(name))?;
+  let tail = StderrTail::default();
+  let stdout = read_lines_async(child.stdout.take(), handlers.stdout);
+  let stderr = read_lines_async(
+    child.stderr.take(),
+    Some(tail.recorder(handlers.stderr)),
+  );
+  let run = async {
+    let (stdout, stderr, status) =
+      tokio::join!(stdout, stderr, child.wait());
+    stdout.and(stderr).map_err(io_error(name))?;
+    status.map_err(io_error(name))
+  };
+  let status = match deadline {
+    None => run.await?,
+    Some(deadline) => {
+      match tokio::time::timeout_at(deadline.into(), run).await {
+        Ok(status) => status?,
+        Err(_) => {
+          if let Some(pid) = child.id() {
+            kill_process_group(pid);
+          }
+          let _ = child.kill().await;
+          return Err(Error::Timeout {
+            operation: name.to_string(),
+            elapsed: started.elapsed(),
+          });
+        }
+      }
+    }
+  };
+  if !status.success() {
+    return Err(command_failed(name, status, &tail.take()));
+  }
+  Ok(())
+}
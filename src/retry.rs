@@ -0,0 +1,210 @@
+//! Retrying `terraform apply` and `terraform destroy` after transient
+//! failures.
+
+use crate::error::{Error, Result};
+use regex::RegexSet;
+use std::time::Duration;
+
+/// Stderr patterns treated as transient by default: rate limiting, timeouts
+/// and flaky networking.
+const DEFAULT_TRANSIENT_PATTERNS: &[&str] = &[
+  r"(?i)rate ?limit",
+  r"(?i)throttl",
+  r"(?i)too many requests",
+  r"(?i)service unavailable",
+  r"(?i)temporary failure in name resolution",
+  r"(?i)connection reset by peer",
+  r"(?i)i/o timeout",
+  r"(?i)TLS handshake timeout",
+];
+
+/// Longest single delay of the default exponential backoff.
+pub(crate) const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long to wait between attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackoffStrategy {
+  /// Wait the same time before every retry.
+  Fixed(Duration),
+  /// Wait `base`, then twice as long after each further failure, never
+  /// more than `max`.
+  Exponential {
+    /// Delay before the first retry.
+    base: Duration,
+    /// Upper bound on any single delay.
+    max: Duration,
+  },
+}
+
+impl BackoffStrategy {
+  /// Delay before retry number `retry`, counting from 1.
+  fn delay(&self, retry: u32) -> Duration {
+    match *self {
+      BackoffStrategy::Fixed(delay) => delay,
+      BackoffStrategy::Exponential { base, max } => {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        base.saturating_mul(factor).min(max)
+      }
+    }
+  }
+}
+
+/// When and how often to retry a failed `terraform apply` or
+/// `terraform destroy`.
+///
+/// A failure is retried only when its stderr matches one of
+/// `transient_patterns`, or whatever it says when there are none.
+/// `terraform init` is never retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+  /// Total number of attempts, including the first. `1` disables
+  /// retries.
+  pub max_attempts: u32,
+  /// Delay between attempts.
+  pub backoff: BackoffStrategy,
+  /// Regular expressions matched against Terraform's stderr to recognize
+  /// transient failures. Empty to retry every failure.
+  pub transient_patterns: Vec<String>,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy {
+      max_attempts: 1,
+      backoff: BackoffStrategy::Exponential {
+        base: Duration::from_secs(2),
+        max: DEFAULT_MAX_BACKOFF,
+      },
+      transient_patterns: DEFAULT_TRANSIENT_PATTERNS
+        .iter()
+        .map(|p| p.to_string())
+        .collect(),
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// Whether failed attempts may be retried at all.
+  pub(crate) fn enabled(&self) -> bool {
+    self.max_attempts > 1
+  }
+
+  /// Compile `transient_patterns`.
+  pub(crate) fn matcher(&self) -> Result<RegexSet> {
+    RegexSet::new(&self.transient_patterns).map_err(|err| {
+      Error::InvalidOptions(format!(
+        "Invalid transient failure pattern in retry policy: {}",
+        err
+      ))
+    })
+  }
+
+  /// Delay before retrying after failed attempt number `attempt`, or `None`
+  /// when `stderr` does not look transient or no attempts are left.
+  pub(crate) fn next_delay(
+    &self,
+    matcher: &RegexSet,
+    attempt: u32,
+    stderr: &str,
+  ) -> Option<Duration> {
+    let transient = matcher.is_empty() || matcher.is_match(stderr);
+    if attempt >= self.max_attempts || !transient {
+      return None;
+    }
+    Some(self.backoff.delay(attempt))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SECOND: Duration = Duration::from_secs(1);
+
+  #[test]
+  fn fixed_backoff_waits_the_same_every_time() {
+    let backoff = BackoffStrategy::Fixed(SECOND);
+    assert_eq!(backoff.delay(1), SECOND);
+    assert_eq!(backoff.delay(7), SECOND);
+  }
+
+  #[test]
+  fn exponential_backoff_doubles_up_to_its_cap() {
+    let backoff = BackoffStrategy::Exponential {
+      base: SECOND,
+      max: 10 * SECOND,
+    };
+    let delays: Vec<Duration> =
+      (1..=6).map(|retry| backoff.delay(retry)).collect();
+    let secs = |s: u64| Duration::from_secs(s);
+    assert_eq!(
+      delays,
+      [secs(1), secs(2), secs(4), secs(8), secs(10), secs(10)]
+    );
+    assert_eq!(backoff.delay(0), SECOND);
+    assert_eq!(backoff.delay(u32::MAX), 10 * SECOND);
+  }
+
+  #[test]
+  fn default_patterns_recognize_transient_errors() {
+    let policy = RetryPolicy {
+      max_attempts: 3,
+      ..RetryPolicy::default()
+    };
+    let matcher = policy.matcher().unwrap();
+    for stderr in [
+      "Error: RateLimitExceeded: too fast",
+      "Error: 429 Too Many Requests",
+      "Throttling: Rate exceeded",
+      "dial tcp: lookup example.com: Temporary failure in name resolution",
+      "read: connection reset by peer",
+    ] {
+      assert!(
+        policy.next_delay(&matcher, 1, stderr).is_some(),
+        "{}",
+        stderr
+      );
+    }
+    let stderr = "Error: invalid value for variable";
+    assert_eq!(policy.next_delay(&matcher, 1, stderr), None);
+  }
+
+  #[test]
+  fn next_delay_stops_after_the_last_attempt() {
+    let policy = RetryPolicy {
+      max_attempts: 3,
+      backoff: BackoffStrategy::Exponential {
+        base: SECOND,
+        max: 60 * SECOND,
+      },
+      ..RetryPolicy::default()
+    };
+    let matcher = policy.matcher().unwrap();
+    let stderr = "rate limit";
+    assert_eq!(policy.next_delay(&matcher, 1, stderr), Some(SECOND));
+    assert_eq!(policy.next_delay(&matcher, 2, stderr), Some(2 * SECOND));
+    assert_eq!(policy.next_delay(&matcher, 3, stderr), None);
+    assert!(!RetryPolicy::default().enabled());
+  }
+
+  #[test]
+  fn invalid_pattern_is_rejected() {
+    let policy = RetryPolicy {
+      transient_patterns: vec!["(".to_string()],
+      ..RetryPolicy::default()
+    };
+    assert!(matches!(policy.matcher(), Err(Error::InvalidOptions(_))));
+  }
+
+  #[test]
+  fn empty_patterns_retry_every_failure() {
+    let policy = RetryPolicy {
+      max_attempts: 2,
+      transient_patterns: Vec::new(),
+      ..RetryPolicy::default()
+    };
+    let matcher = policy.matcher().unwrap();
+    assert!(policy.next_delay(&matcher, 1, "Error: anything").is_some());
+    assert!(policy.next_delay(&matcher, 2, "Error: anything").is_none());
+  }
+}
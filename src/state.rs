@@ -0,0 +1,103 @@
+//! Parsing of `terraform state list`, `terraform state pull` and
+//! `terraform show -json` output.
+
+use crate::{error::Result, output::value_to_string};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// How far a Terraform configuration has been deployed, as reported by
+/// [`status`](crate::status).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeploymentStatus {
+  /// No work directory exists yet, or `terraform init` has not run in it.
+  NotInitialized,
+  /// Terraform is initialized but nothing has been applied.
+  Initialized,
+  /// The state holds resources.
+  Deployed {
+    /// Number of resource instances in the state, as listed by
+    /// `terraform state list`.
+    resource_count: u32,
+  },
+  /// A state exists but holds no resources, as left after a destroy.
+  Destroyed,
+}
+
+/// Derive the [`DeploymentStatus`] of an initialized work directory from
+/// the stdout of `terraform state pull`, which is empty when no state
+/// exists yet.
+pub(crate) fn deployment_status(stdout: &[u8]) -> Result<DeploymentStatus> {
+  if String::from_utf8_lossy(stdout).trim().is_empty() {
+    return Ok(DeploymentStatus::Initialized);
+  }
+  let state: Value = serde_json::from_slice(stdout)?;
+  let resource_count = state
+    .get("resources")
+    .and_then(Value::as_array)
+    .into_iter()
+    .flatten()
+    .map(|resource| {
+      resource
+        .get("instances")
+        .and_then(Value::as_array)
+        .map_or(0, Vec::len)
+    })
+    .sum::<usize>();
+  Ok(match u32::try_from(resource_count).unwrap_or(u32::MAX) {
+    0 => DeploymentStatus::Destroyed,
+    resource_count => DeploymentStatus::Deployed { resource_count },
+  })
+}
+
+/// Split the stdout of `terraform state list` into resource addresses.
+pub(crate) fn parse_state_list(stdout: &[u8]) -> Vec<String> {
+  String::from_utf8_lossy(stdout)
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty())
+    .map(str::to_string)
+    .collect()
+}
+
+/// Find the resource at `address` in the stdout of `terraform show -json`
+/// and render each of its attributes as a string.
+///
+/// Returns `None` when the state has no such resource.
+pub(crate) fn resource_attributes(
+  stdout: &[u8],
+  address: &str,
+) -> Result<Option<HashMap<String, String>>> {
+  let show: Value = serde_json::from_slice(stdout)?;
+  let Some(root) = show.pointer("/values/root_module") else {
+    return Ok(None);
+  };
+  Ok(find_resource(root, address).map(|resource| {
+    resource
+      .get("values")
+      .and_then(Value::as_object)
+      .map(|values| {
+        values
+          .iter()
+          .map(|(key, value)| (key.clone(), value_to_string(value)))
+          .collect()
+      })
+      .unwrap_or_default()
+  }))
+}
+
+/// Search `module` and its child modules for the resource at `address`.
+fn find_resource<'a>(module: &'a Value, address: &str) -> Option<&'a Value> {
+  let resources = module.get("resources").and_then(Value::as_array);
+  if let Some(resource) = resources.into_iter().flatten().find(|resource| {
+    resource.get("address").and_then(Value::as_str) == Some(address)
+  }) {
+    return Some(resource);
+  }
+  module
+    .get("child_modules")
+    .and_then(Value::as_array)
+    .into_iter()
+    .flatten()
+    .find_map(|child| find_resource(child, address))
+}
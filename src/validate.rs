@@ -0,0 +1,109 @@
+//! Parsing of `terraform validate -json` output.
+
+use crate::error::Result;
+use serde_json::Value;
+use std::fmt;
+
+/// Location in a configuration file a diagnostic refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticRange {
+  /// File the diagnostic was raised in, relative to the module root.
+  pub filename: String,
+  /// 1-based line where the range starts.
+  pub start_line: u64,
+  /// 1-based column where the range starts.
+  pub start_column: u64,
+  /// 1-based line where the range ends.
+  pub end_line: u64,
+  /// 1-based column where the range ends.
+  pub end_column: u64,
+}
+
+/// A single diagnostic reported by `terraform validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  /// Either `error` or `warning`.
+  pub severity: String,
+  /// One-line description of the problem.
+  pub summary: String,
+  /// Longer explanation, possibly empty.
+  pub detail: String,
+  /// Where the problem is, when Terraform knows.
+  pub range: Option<DiagnosticRange>,
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {}", self.severity, self.summary)?;
+    if let Some(range) = &self.range {
+      write!(
+        f,
+        " ({}:{}:{})",
+        range.filename, range.start_line, range.start_column
+      )?;
+    }
+    if !self.detail.is_empty() {
+      write!(f, "\n  {}", self.detail)?;
+    }
+    Ok(())
+  }
+}
+
+/// Error returned when `terraform validate` rejects a configuration.
+///
+/// Returned as [`crate::Error::Validation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+  /// Every diagnostic Terraform reported, warnings included.
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for ValidationError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Terraform configuration is invalid")?;
+    for diagnostic in &self.diagnostics {
+      write!(f, "\n{}", diagnostic)?;
+    }
+    Ok(())
+  }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Result of `terraform validate -json`.
+pub(crate) struct ParsedValidation {
+  pub(crate) valid: bool,
+  pub(crate) diagnostics: Vec<Diagnostic>,
+}
+
+/// Parse the JSON document written by `terraform validate -json`.
+pub(crate) fn parse_validation(stdout: &[u8]) -> Result<ParsedValidation> {
+  let raw: Value = serde_json::from_slice(stdout)?;
+  let valid = raw.get("valid").and_then(Value::as_bool).unwrap_or(false);
+  let text = |v: &Value, key: &str| {
+    v.get(key).and_then(Value::as_str).unwrap_or_default().to_string()
+  };
+  let number = |v: &Value, key: &str| {
+    v.get(key).and_then(Value::as_u64).unwrap_or_default()
+  };
+  let diagnostics = raw
+    .get("diagnostics")
+    .and_then(Value::as_array)
+    .map(Vec::as_slice)
+    .unwrap_or_default()
+    .iter()
+    .map(|d| Diagnostic {
+      severity: text(d, "severity"),
+      summary: text(d, "summary"),
+      detail: text(d, "detail"),
+      range: d.get("range").map(|r| DiagnosticRange {
+        filename: text(r, "filename"),
+        start_line: number(&r["start"], "line"),
+        start_column: number(&r["start"], "column"),
+        end_line: number(&r["end"], "line"),
+        end_column: number(&r["end"], "column"),
+      }),
+    })
+    .collect();
+  Ok(ParsedValidation { valid, diagnostics })
+}
@@ -0,0 +1,959 @@
+//! Temporary work directories Terraform runs in.
+
+use crate::error::{Result, WorkspaceContext};
+use crate::{Error, LogConfig};
+use fs2::FileExt;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use sha2::{Digest, Sha256};
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap},
+  fs::{self, File, OpenOptions},
+  io::{self, Write},
+  path::{Component, Path, PathBuf},
+  process, thread,
+  time::{Duration, Instant, SystemTime},
+};
+
+/// File inside a work directory mapping the path of every source file it
+/// was copied from to its SHA-256 digest, as a JSON object.
+const MANIFEST_FILE: &str = "atar-manifest.json";
+
+/// SHA-256 digest of every source file, keyed by its path relative to the
+/// module root.
+///
+/// Sorted by path, so it does not depend on directory order.
+type Manifest = BTreeMap<String, String>;
+
+/// File inside a work directory holding the path of the sources it was
+/// copied from.
+const SOURCE_FILE: &str = ".atar-source";
+
+/// How often a locked work directory is checked again.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Directory inside a work directory holding copies of the var-files.
+///
+/// Kept out of the module root so Terraform never loads them on its own.
+const VAR_FILES_DIR: &str = ".atar-var-files";
+
+/// File inside a work directory the variables are written to while a
+/// command runs, when they are kept off the command line.
+///
+/// Not named `*.auto.tfvars.json`, so Terraform only reads it when told to.
+const VARS_FILE: &str = ".atar-vars.tfvars.json";
+
+/// Files Terraform creates in the work directory, carried over when the
+/// copied sources are refreshed so existing state is never lost. They win
+/// over any copy of the same name from the sources.
+const GENERATED_FILES: &[&str] = &[
+  ".terraform",
+  ".terraform.lock.hcl",
+  "terraform.tfstate",
+  "terraform.tfstate.backup",
+  "terraform.tfstate.d",
+  crate::PLAN_FILE,
+];
+
+/// File at the root of the sources listing paths to leave out of the work
+/// directory, in `.gitignore` syntax.
+const IGNORE_FILE: &str = ".terraformignore";
+
+/// File at the root of the sources listing further paths to leave out, read
+/// on top of [`IGNORE_FILE`] or [`DEFAULT_IGNORES`]. Unlike
+/// [`IGNORE_FILE`], Terraform itself never reads it.
+const ATAR_IGNORE_FILE: &str = ".atarignore";
+
+/// Paths left out of the work directory when the sources have no
+/// [`IGNORE_FILE`], on top of [`ALWAYS_IGNORES`]: what Terraform skips when
+/// packaging modules.
+const DEFAULT_IGNORES: &[&str] = &[".git/"];
+
+/// Paths always left out of the work directory, whatever the ignore files
+/// say: local state and providers that would shadow the work directory's
+/// own.
+const ALWAYS_IGNORES: &[&str] =
+  &[".terraform/", "*.tfstate", "*.tfstate.backup"];
+
+/// Which source paths are left out of the work directory.
+struct IgnoreRules(Gitignore);
+
+impl IgnoreRules {
+  /// Read the [`IGNORE_FILE`] of `src_dir`, falling back to
+  /// [`DEFAULT_IGNORES`] if there is none, then its [`ATAR_IGNORE_FILE`], the
+  /// `extra` patterns and last [`ALWAYS_IGNORES`], so none of them can be
+  /// negated.
+  fn load(src_dir: &Path, extra: &[String]) -> Result<IgnoreRules> {
+    let mut builder = GitignoreBuilder::new(src_dir);
+    let file = src_dir.join(IGNORE_FILE);
+    if file.is_file() {
+      add_ignore_file(&mut builder, &file)?;
+    } else {
+      for pattern in DEFAULT_IGNORES {
+        builder
+          .add_line(None, pattern)
+          .map_err(io::Error::other)
+          .context("Invalid default ignore pattern")?;
+      }
+    }
+    let file = src_dir.join(ATAR_IGNORE_FILE);
+    if file.is_file() {
+      add_ignore_file(&mut builder, &file)?;
+    }
+    for pattern in extra {
+      builder.add_line(None, pattern).map_err(|err| {
+        Error::InvalidOptions(format!(
+          "Invalid ignore pattern `{}`: {}",
+          pattern, err
+        ))
+      })?;
+    }
+    for pattern in ALWAYS_IGNORES {
+      builder
+        .add_line(None, pattern)
+        .map_err(io::Error::other)
+        .context("Invalid default ignore pattern")?;
+    }
+    let rules = builder
+      .build()
+      .map_err(io::Error::other)
+      .context("Invalid ignore patterns")?;
+    Ok(IgnoreRules(rules))
+  }
+
+  /// Whether `path`, a directory if `is_dir`, is left out.
+  fn skips(&self, path: &Path, is_dir: bool) -> bool {
+    self.0.matched(path, is_dir).is_ignore()
+  }
+
+  /// Whether the resolved symlink `target` is left out, either itself or
+  /// as part of a directory that is, so links cannot pull in ignored
+  /// files such as local state.
+  fn skips_target(&self, target: &Path) -> bool {
+    self.skips(target, target.is_dir())
+      || target.ancestors().skip(1).any(|dir| self.skips(dir, true))
+  }
+}
+
+/// Add the patterns of the ignore file at `file` to `builder`.
+fn add_ignore_file(builder: &mut GitignoreBuilder, file: &Path) -> Result<()> {
+  match builder.add(file) {
+    Some(err) => Err(io::Error::other(err))
+      .with_context(|| format!("Invalid ignore file {:?}", file)),
+    None => Ok(()),
+  }
+}
+
+/// Recursively copy a directory tree from `src` to `dst`, leaving out what
+/// `rules` ignore.
+///
+/// Files keep their permission bits, so bundled scripts stay executable,
+/// and symlinks are recreated as symlinks where the platform allows.
+fn copy_dir_recursive(
+  src: &Path,
+  dst: &Path,
+  rules: &IgnoreRules,
+) -> Result<()> {
+  fs::create_dir_all(dst)
+    .with_context(|| format!("Failed to create directory {:?}", dst))?;
+  for entry in fs::read_dir(src)
+    .with_context(|| format!("Failed to read directory {:?}", src))?
+  {
+    let entry =
+      entry.with_context(|| format!("Failed to access entry in {:?}", src))?;
+    let path = entry.path();
+    let dest = dst.join(entry.file_name());
+    let is_dir = path.is_dir();
+    if rules.skips(&path, is_dir) {
+      continue;
+    }
+    if is_symlink(&entry)? {
+      let target = symlink_target(&path)?;
+      if !rules.skips_target(&target) {
+        copy_symlink(&target, &dest, rules)?;
+      }
+    } else if is_dir {
+      copy_dir_recursive(&path, &dest, rules)?;
+    } else {
+      fs::copy(&path, &dest).with_context(|| {
+        format!("Failed to copy file {:?} to {:?}", path, dest)
+      })?;
+    }
+  }
+  Ok(())
+}
+
+/// Whether the directory entry `entry` is a symlink, without following it.
+fn is_symlink(entry: &fs::DirEntry) -> Result<bool> {
+  let file_type = entry.file_type().with_context(|| {
+    format!("Failed to read file type of {:?}", entry.path())
+  })?;
+  Ok(file_type.is_symlink())
+}
+
+/// Absolute path the symlink `link` resolves to, failing if it is broken.
+fn symlink_target(link: &Path) -> Result<PathBuf> {
+  fs::canonicalize(link)
+    .with_context(|| format!("Broken symlink {:?} in Terraform sources", link))
+}
+
+/// Recreate a symlink at `dest`, pointing at the absolute path `target` it
+/// resolves to so that relative links still resolve from the work
+/// directory.
+///
+/// Where symlinks cannot be created, the target is copied instead, leaving
+/// out what `rules` ignore.
+#[cfg_attr(unix, allow(unused_variables))]
+fn copy_symlink(target: &Path, dest: &Path, rules: &IgnoreRules) -> Result<()> {
+  #[cfg(unix)]
+  {
+    std::os::unix::fs::symlink(target, dest).with_context(|| {
+      format!("Failed to create symlink {:?} to {:?}", dest, target)
+    })
+  }
+  #[cfg(not(unix))]
+  if target.is_dir() {
+    copy_dir_recursive(target, dest, rules)
+  } else {
+    fs::copy(target, dest).map(drop).with_context(|| {
+      format!("Failed to copy file {:?} to {:?}", target, dest)
+    })
+  }
+}
+
+/// Collect the SHA-256 digest of every file under `dir` not ignored by
+/// `rules`, keyed by its path relative to `root`.
+fn collect_digests(
+  root: &Path,
+  dir: &Path,
+  rules: &IgnoreRules,
+  digests: &mut Vec<(String, String)>,
+) -> Result<()> {
+  for entry in fs::read_dir(dir)
+    .with_context(|| format!("Failed to read directory {:?}", dir))?
+  {
+    let entry =
+      entry.with_context(|| format!("Failed to access entry in {:?}", dir))?;
+    let path = entry.path();
+    let is_dir = path.is_dir();
+    if rules.skips(&path, is_dir) {
+      continue;
+    }
+    let relative = path.strip_prefix(root).unwrap_or(&path);
+    let relative = relative.to_string_lossy().into_owned();
+    if is_symlink(&entry)? {
+      let target = symlink_target(&path)?;
+      if rules.skips_target(&target) {
+        continue;
+      }
+      if cfg!(unix) {
+        // The work directory links to the target, so only a new target
+        // calls for a refresh.
+        let target = target.to_string_lossy();
+        let digest = format!("{:x}", Sha256::digest(target.as_bytes()));
+        digests.push((relative, digest));
+        continue;
+      }
+    }
+    if is_dir {
+      collect_digests(root, &path, rules, digests)?;
+    } else {
+      let bytes = fs::read(&path)
+        .with_context(|| format!("Failed to read file {:?}", path))?;
+      let mut hasher = Sha256::new();
+      hasher.update(&bytes);
+      // Permission bits are copied too, so e.g. making a script executable
+      // calls for a refresh.
+      #[cfg(unix)]
+      {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = entry
+          .metadata()
+          .with_context(|| format!("Failed to read metadata of {:?}", path))?;
+        hasher.update(metadata.permissions().mode().to_le_bytes());
+      }
+      digests.push((relative, format!("{:x}", hasher.finalize())));
+    }
+  }
+  Ok(())
+}
+
+/// The SHA-256 digest of every file under `src_dir` not ignored by
+/// `rules`.
+fn source_manifest(src_dir: &Path, rules: &IgnoreRules) -> Result<Manifest> {
+  let mut digests = Vec::new();
+  collect_digests(src_dir, src_dir, rules, &mut digests)?;
+  Ok(digests.into_iter().collect())
+}
+
+/// `manifest` as written to [`MANIFEST_FILE`].
+fn manifest_json(manifest: &Manifest) -> String {
+  // Maps with string keys always serialize
+  serde_json::to_string_pretty(manifest).unwrap_or_default()
+}
+
+/// The manifest recorded in the work directory `work`, if it has a valid
+/// one.
+fn recorded_manifest(work: &Path) -> Option<Manifest> {
+  let json = fs::read_to_string(work.join(MANIFEST_FILE)).ok()?;
+  serde_json::from_str(&json).ok()
+}
+
+/// Paths whose digest differs between two manifests, including files only
+/// present in one of them, sorted.
+fn changed_files<'a>(
+  recorded: &'a Manifest,
+  current: &'a Manifest,
+) -> Vec<&'a str> {
+  let paths: BTreeSet<&str> = recorded
+    .keys()
+    .chain(current.keys())
+    .map(String::as_str)
+    .collect();
+  paths
+    .into_iter()
+    .filter(|path| recorded.get(*path) != current.get(*path))
+    .collect()
+}
+
+/// Fill a fresh directory next to `work` with `copy` and move it into
+/// place, keeping any Terraform state from the previous copy.
+///
+/// `src_dir` is recorded as the source of the work directory, if known.
+/// The state is only moved over once everything else is written, and moved
+/// back if the swap fails, so an error never loses it.
+fn replace_work_dir(
+  src_dir: Option<&Path>,
+  work: &Path,
+  manifest: &str,
+  copy: impl FnOnce(&Path) -> Result<()>,
+) -> Result<()> {
+  let staging = work.with_extension(format!("{}.tmp", process::id()));
+  let _ = fs::remove_dir_all(&staging);
+  let populate = || -> Result<()> {
+    copy(&staging)?;
+    if let Some(src_dir) = src_dir {
+      let source = src_dir.to_string_lossy();
+      fs::write(staging.join(SOURCE_FILE), source.as_bytes())
+        .context("Failed to record workspace source directory")?;
+    }
+    fs::write(staging.join(MANIFEST_FILE), manifest)
+      .context("Failed to record workspace source manifest")
+  };
+  if let Err(err) = populate() {
+    let _ = fs::remove_dir_all(&staging);
+    return Err(err);
+  }
+
+  let carried = match carry_over(work, &staging) {
+    Ok(carried) => carried,
+    Err(err) => {
+      let _ = fs::remove_dir_all(&staging);
+      return Err(err);
+    }
+  };
+  let stale = work.with_extension(format!("{}.old", process::id()));
+  let swap = || -> Result<()> {
+    if work.exists() {
+      fs::rename(work, &stale)
+        .with_context(|| format!("Failed to move aside {:?}", work))?;
+    }
+    let moved = fs::rename(&staging, work)
+      .with_context(|| format!("Failed to move {:?} into place", staging));
+    if moved.is_err() {
+      let _ = fs::rename(&stale, work);
+    }
+    moved
+  };
+  if let Err(err) = swap() {
+    // Left in place when the state cannot be put back, to recover by hand
+    if restore(work, &staging, &carried) {
+      let _ = fs::remove_dir_all(&staging);
+    }
+    return Err(err);
+  }
+  let _ = fs::remove_dir_all(&stale);
+  Ok(())
+}
+
+/// Move the [`GENERATED_FILES`] of `work` into `staging`, replacing copies
+/// from the sources, and return the names moved. On failure, those already
+/// moved are put back.
+fn carry_over(work: &Path, staging: &Path) -> Result<Vec<&'static str>> {
+  let mut carried = Vec::new();
+  for name in GENERATED_FILES {
+    let (old, new) = (work.join(name), staging.join(name));
+    if !old.exists() {
+      continue;
+    }
+    if let Err(err) = remove_path(&new).and_then(|()| fs::rename(&old, &new)) {
+      restore(work, staging, &carried);
+      return Err(err).with_context(|| {
+        format!("Failed to carry over {:?} to {:?}", old, new)
+      });
+    }
+    carried.push(*name);
+  }
+  Ok(carried)
+}
+
+/// Delete the file or directory at `path`, if any.
+fn remove_path(path: &Path) -> io::Result<()> {
+  match fs::symlink_metadata(path) {
+    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+    Ok(_) => fs::remove_file(path),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+    Err(err) => Err(err),
+  }
+}
+
+/// Move the files `names` carried over into `staging` back to `work`,
+/// returning whether all of them made it.
+fn restore(work: &Path, staging: &Path, names: &[&str]) -> bool {
+  names.iter().fold(true, |restored, name| {
+    let moved = fs::rename(staging.join(name), work.join(name)).is_ok();
+    restored && moved
+  })
+}
+
+/// Exclusive lock on a work directory, released when dropped.
+#[derive(Debug)]
+pub(crate) struct WorkspaceLock(File);
+
+impl Drop for WorkspaceLock {
+  fn drop(&mut self) {
+    let _ = FileExt::unlock(&self.0);
+  }
+}
+
+/// Lock the work directory `work` against other atar processes, waiting up
+/// to `timeout` for a current holder to release it.
+///
+/// The lock file sits next to the work directory rather than inside it, as
+/// the directory itself is replaced whenever the sources change.
+pub(crate) fn lock_work_dir(
+  work: &Path,
+  timeout: Duration,
+) -> Result<WorkspaceLock> {
+  let path = work.with_extension("lock");
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)
+      .with_context(|| format!("Failed to create directory {:?}", parent))?;
+  }
+  let file = File::create(&path)
+    .with_context(|| format!("Failed to open lock file {:?}", path))?;
+  let started = Instant::now();
+  while file.try_lock_exclusive().is_err() {
+    if started.elapsed() >= timeout {
+      return Err(Error::WorkspaceLocked {
+        work_dir: work.to_path_buf(),
+        timeout,
+      });
+    }
+    thread::sleep(LOCK_POLL_INTERVAL);
+  }
+  Ok(WorkspaceLock(file))
+}
+
+/// Work directory under `root` for the sources in the canonical `src_dir`,
+/// named after a hash of its normalized path.
+pub(crate) fn work_dir_path(src_dir: &Path, root: &Path) -> PathBuf {
+  let mut hasher = Sha256::new();
+  hasher.update(normalized_path(src_dir).as_bytes());
+  root.join(format!("{:x}", hasher.finalize()))
+}
+
+/// `path` written with `/` separators and, on Windows, lowercased and
+/// without the `\\?\` prefix `canonicalize` adds, so one logical path always
+/// hashes the same.
+///
+/// Callers pass canonical paths, so `.`, `..`, symlinks and trailing
+/// separators are already resolved. Other platforms keep the path as it is:
+/// a `\` there is part of a name, and case may matter.
+fn normalized_path(path: &Path) -> String {
+  let path = path.to_string_lossy();
+  if !cfg!(windows) {
+    return path.into_owned();
+  }
+  let path = match path.strip_prefix(r"\\?\UNC\") {
+    Some(share) => format!(r"\\{}", share),
+    None => path.strip_prefix(r"\\?\").unwrap_or(&path).to_string(),
+  };
+  path.replace('\\', "/").to_lowercase()
+}
+
+/// Delete the work directory `work`, along with its copied sources, provider
+/// cache and state.
+pub(crate) fn remove_work_dir(work: &Path) -> Result<()> {
+  if work.exists() {
+    fs::remove_dir_all(work)
+      .with_context(|| format!("Failed to remove work directory {:?}", work))?;
+  }
+  Ok(())
+}
+
+/// Whether `path` is a work directory atar filled with sources.
+pub(crate) fn is_work_dir(path: &Path) -> bool {
+  path.join(MANIFEST_FILE).is_file()
+}
+
+/// Whether `name` looks like a directory made by [`work_dir_path`].
+fn is_work_dir_name(name: &str) -> bool {
+  name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// A work directory atar created under the work root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkspaceInfo {
+  /// The work directory itself.
+  pub path: PathBuf,
+  /// When the directory was created, which is also when the sources were
+  /// last copied into it.
+  pub created_at: SystemTime,
+  /// When an atar operation last ran in the directory.
+  pub last_used: SystemTime,
+  /// Directory the sources were copied from, unknown for work directories
+  /// created by older versions of atar or deployed from in-memory files.
+  pub source_dir: Option<PathBuf>,
+  /// Total size of the files inside, state and providers included.
+  pub size_bytes: u64,
+}
+
+/// The work directories under `root`, least recently used first.
+///
+/// Entries atar did not create are ignored. A missing `root` has none.
+pub(crate) fn list_work_dirs(root: &Path) -> Result<Vec<WorkspaceInfo>> {
+  let entries = match fs::read_dir(root) {
+    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+      return Ok(Vec::new());
+    }
+    entries => entries.with_context(|| format!("Failed to read {:?}", root))?,
+  };
+  let mut infos = Vec::new();
+  for entry in entries {
+    let entry =
+      entry.with_context(|| format!("Failed to read entry in {:?}", root))?;
+    let path = entry.path();
+    if !is_work_dir_name(&entry.file_name().to_string_lossy()) {
+      continue;
+    }
+    let metadata = entry
+      .metadata()
+      .with_context(|| format!("Failed to read metadata of {:?}", path))?;
+    if !metadata.is_dir() {
+      continue;
+    }
+    let modified = metadata
+      .modified()
+      .with_context(|| format!("Failed to read mtime of {:?}", path))?;
+    // Locking truncates the lock file, so its mtime tracks every operation.
+    let last_used = fs::metadata(path.with_extension("lock"))
+      .and_then(|lock| lock.modified())
+      .map_or(modified, |locked| locked.max(modified));
+    infos.push(WorkspaceInfo {
+      created_at: metadata.created().unwrap_or(modified),
+      last_used,
+      source_dir: fs::read_to_string(path.join(SOURCE_FILE))
+        .ok()
+        .map(PathBuf::from),
+      size_bytes: dir_size(&path),
+      path,
+    });
+  }
+  infos.sort_by_key(|info| info.last_used);
+  Ok(infos)
+}
+
+/// Total size of the files under `dir`, not following symlinks and
+/// skipping anything that cannot be read.
+fn dir_size(dir: &Path) -> u64 {
+  let Ok(entries) = fs::read_dir(dir) else {
+    return 0;
+  };
+  entries
+    .filter_map(|entry| entry.ok())
+    .filter_map(|entry| Some((entry.path(), entry.file_type().ok()?)))
+    .map(|(path, file_type)| {
+      if file_type.is_dir() {
+        dir_size(&path)
+      } else {
+        fs::symlink_metadata(&path).map_or(0, |m| m.len())
+      }
+    })
+    .sum()
+}
+
+/// Delete the work directories under `root` not used for `older_than`,
+/// returning their paths.
+///
+/// Directories locked by a running atar process are left alone, as is
+/// anything under `root` that atar did not create. Lock files are kept:
+/// unlinking one while holding it would let the next process lock a new
+/// file while a waiter still locks the old one.
+pub(crate) fn remove_stale_work_dirs(
+  root: &Path,
+  older_than: Duration,
+) -> Result<Vec<PathBuf>> {
+  let mut removed = Vec::new();
+  for info in list_work_dirs(root)? {
+    let age = info.last_used.elapsed().unwrap_or_default();
+    if age < older_than {
+      continue;
+    }
+    let path = info.path;
+    let Ok(_lock) = lock_work_dir(&path, Duration::ZERO) else {
+      tracing::debug!("Skipping {:?}, in use", path);
+      continue;
+    };
+    remove_work_dir(&path)?;
+    removed.push(path);
+  }
+  Ok(removed)
+}
+
+/// Prepare a deterministic workspace under `root` based on the source
+/// directory path, locked for the caller.
+///
+/// The workspace is refreshed whenever any file in `src_dir` is added,
+/// removed or modified, as recorded in its manifest. Paths matched by a
+/// `.terraformignore` or `.atarignore` in `src_dir` or by `ignores` are
+/// neither copied nor tracked.
+pub(crate) fn prepare_work_dir(
+  src_dir: &Path,
+  root: &Path,
+  ignores: &[String],
+  log: &LogConfig,
+  lock_timeout: Duration,
+) -> Result<(PathBuf, WorkspaceLock)> {
+  let work = work_dir_path(src_dir, root);
+  let lock = lock_work_dir(&work, lock_timeout)?;
+  let rules = IgnoreRules::load(src_dir, ignores)?;
+  let current = source_manifest(src_dir, &rules)?;
+  let recorded = recorded_manifest(&work);
+  if recorded.as_ref() != Some(&current) {
+    if work.exists() {
+      log.info(format_args!(
+        "Terraform sources changed, refreshing temporary directory {}",
+        work.display()
+      ));
+      let recorded = recorded.unwrap_or_default();
+      for path in changed_files(&recorded, &current) {
+        tracing::debug!("Changed: {}", path);
+      }
+    } else {
+      log.info(format_args!(
+        "Copying Terraform files to temporary directory {}",
+        work.display()
+      ));
+    }
+    let manifest = manifest_json(&current);
+    replace_work_dir(Some(src_dir), &work, &manifest, |staging| {
+      copy_dir_recursive(src_dir, staging, &rules)
+    })?;
+  }
+  Ok((work, lock))
+}
+
+/// Write the in-memory sources `files` into a work directory under `root`
+/// and return its path, locked for the caller.
+///
+/// The directory is named after a hash of the file contents, so the same
+/// files always share one work directory and its state. Paths must be
+/// relative and stay inside the module root.
+pub(crate) fn prepare_files_work_dir(
+  files: &HashMap<PathBuf, Vec<u8>>,
+  root: &Path,
+  log: &LogConfig,
+  lock_timeout: Duration,
+) -> Result<(PathBuf, WorkspaceLock)> {
+  let manifest = files_manifest(files)?;
+  let json = manifest_json(&manifest);
+  let work = root.join(format!("{:x}", Sha256::digest(json.as_bytes())));
+  let lock = lock_work_dir(&work, lock_timeout)?;
+  if recorded_manifest(&work).as_ref() != Some(&manifest) {
+    log.info(format_args!(
+      "Writing Terraform files to temporary directory {}",
+      work.display()
+    ));
+    replace_work_dir(None, &work, &json, |staging| {
+      for (path, contents) in files {
+        let dest = staging.join(path);
+        if let Some(parent) = dest.parent() {
+          fs::create_dir_all(parent).with_context(|| {
+            format!("Failed to create directory {:?}", parent)
+          })?;
+        }
+        fs::write(&dest, contents)
+          .with_context(|| format!("Failed to write file {:?}", dest))?;
+      }
+      Ok(())
+    })?;
+  }
+  Ok((work, lock))
+}
+
+/// Manifest of the in-memory sources `files`, in the format of
+/// [`source_manifest`], after checking their paths.
+fn files_manifest(files: &HashMap<PathBuf, Vec<u8>>) -> Result<Manifest> {
+  let mut has_tf = false;
+  let mut digests = Vec::new();
+  for (path, contents) in files {
+    let inside = path.components().next().is_some()
+      && path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !inside {
+      return Err(Error::InvalidOptions(format!(
+        "Invalid Terraform file path {:?}: must be relative and stay inside \
+         the module root",
+        path
+      )));
+    }
+    let relative = normalized_path(path);
+    has_tf |= relative.ends_with(".tf") || relative.ends_with(".tf.json");
+    digests.push((relative, format!("{:x}", Sha256::digest(contents))));
+  }
+  if !has_tf {
+    return Err(Error::InvalidOptions(
+      "No Terraform files (*.tf, *.tf.json) among the provided files"
+        .to_string(),
+    ));
+  }
+  Ok(digests.into_iter().collect())
+}
+
+/// Copy `var_files` into `work_dir`, returning their paths relative to it.
+///
+/// Copies are prefixed with their position so that files sharing a name do
+/// not overwrite each other.
+pub(crate) fn copy_var_files(
+  work_dir: &Path,
+  var_files: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+  if var_files.is_empty() {
+    return Ok(Vec::new());
+  }
+  let dir = work_dir.join(VAR_FILES_DIR);
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir)
+    .with_context(|| format!("Failed to create directory {:?}", dir))?;
+  let mut copied = Vec::with_capacity(var_files.len());
+  for (i, file) in var_files.iter().enumerate() {
+    let name = file
+      .file_name()
+      .with_context(|| format!("Invalid var-file path {:?}", file))?;
+    let relative = Path::new(VAR_FILES_DIR)
+      .join(format!("{}-{}", i, name.to_string_lossy()));
+    fs::copy(file, work_dir.join(&relative)).with_context(|| {
+      format!("Failed to copy var-file {:?} into {:?}", file, work_dir)
+    })?;
+    copied.push(relative);
+  }
+  Ok(copied)
+}
+
+/// Variables file written by [`write_vars_file`], removed when dropped.
+#[derive(Debug)]
+pub(crate) struct VarsFile(PathBuf);
+
+impl Drop for VarsFile {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.0);
+  }
+}
+
+/// Path of the variables file inside `work_dir`, relative to it.
+pub(crate) fn vars_file_path() -> &'static Path {
+  Path::new(VARS_FILE)
+}
+
+/// Write `vars` as a `.tfvars.json` file inside `work_dir`, readable by the
+/// current user only.
+pub(crate) fn write_vars_file(
+  work_dir: &Path,
+  vars: &HashMap<String, String>,
+) -> Result<VarsFile> {
+  let path = work_dir.join(VARS_FILE);
+  // The mode only applies on creation, so a file left by a killed run
+  // must not be reused as is.
+  match fs::remove_file(&path) {
+    Err(err) if err.kind() != io::ErrorKind::NotFound => {
+      return Err(err).with_context(|| {
+        format!("Failed to remove stale variables file {:?}", path)
+      });
+    }
+    _ => {}
+  }
+  let mut open = OpenOptions::new();
+  open.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut open, 0o600);
+  let file = VarsFile(path);
+  let mut out = open
+    .open(&file.0)
+    .with_context(|| format!("Failed to create variables file {:?}", file.0))?;
+  serde_json::to_writer(&mut out, vars)
+    .map_err(io::Error::from)
+    .and_then(|_| out.flush())
+    .with_context(|| format!("Failed to write variables file {:?}", file.0))?;
+  Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A work directory under a fresh temporary root, holding some state.
+  fn deployed_work_dir() -> (tempfile::TempDir, PathBuf) {
+    let root = tempfile::tempdir().unwrap();
+    let work = root.path().join("work");
+    fs::create_dir(&work).unwrap();
+    fs::write(work.join("main.tf"), "old").unwrap();
+    fs::write(work.join("terraform.tfstate"), "state").unwrap();
+    (root, work)
+  }
+
+  #[test]
+  fn replace_work_dir_carries_state_over() {
+    let (_root, work) = deployed_work_dir();
+    replace_work_dir(None, &work, "manifest", |staging| {
+      fs::create_dir_all(staging).context("create")?;
+      fs::write(staging.join("main.tf"), "new").context("write")
+    })
+    .unwrap();
+    assert_eq!(fs::read_to_string(work.join("main.tf")).unwrap(), "new");
+    let state = fs::read_to_string(work.join("terraform.tfstate")).unwrap();
+    assert_eq!(state, "state");
+  }
+
+  #[test]
+  fn replace_work_dir_prefers_generated_files_over_copies() {
+    let (_root, work) = deployed_work_dir();
+    fs::write(work.join(".terraform.lock.hcl"), "locked").unwrap();
+    replace_work_dir(None, &work, "manifest", |staging| {
+      fs::create_dir_all(staging).context("create")?;
+      fs::write(staging.join(".terraform.lock.hcl"), "copied").context("write")
+    })
+    .unwrap();
+    let lock = fs::read_to_string(work.join(".terraform.lock.hcl")).unwrap();
+    assert_eq!(lock, "locked");
+  }
+
+  #[test]
+  fn manifest_is_a_json_map_of_digests() {
+    let src = tempfile::tempdir().unwrap();
+    fs::write(src.path().join("main.tf"), "").unwrap();
+    let rules = IgnoreRules::load(src.path(), &[]).unwrap();
+    let manifest = source_manifest(src.path(), &rules).unwrap();
+    let json: HashMap<String, String> =
+      serde_json::from_str(&manifest_json(&manifest)).unwrap();
+    assert_eq!(json.len(), 1);
+    let digest = &json["main.tf"];
+    assert_eq!(digest.len(), 64);
+    assert!(digest.bytes().all(|b| b.is_ascii_hexdigit()));
+  }
+
+  #[test]
+  fn changed_files_lists_added_removed_and_modified_paths() {
+    let manifest = |entries: &[(&str, &str)]| -> Manifest {
+      entries
+        .iter()
+        .map(|(path, digest)| (path.to_string(), digest.to_string()))
+        .collect()
+    };
+    let recorded = manifest(&[("a.tf", "1"), ("b.tf", "2"), ("c.tf", "3")]);
+    let current = manifest(&[("a.tf", "1"), ("b.tf", "9"), ("d.tf", "4")]);
+    let changed = changed_files(&recorded, &current);
+    assert_eq!(changed, ["b.tf", "c.tf", "d.tf"]);
+  }
+
+  #[test]
+  fn ignore_file_cannot_bring_back_local_state() {
+    let src = tempfile::tempdir().unwrap();
+    fs::write(src.path().join(IGNORE_FILE), "!*.tfstate\n").unwrap();
+    let rules = IgnoreRules::load(src.path(), &["!.terraform/".into()]);
+    let rules = rules.unwrap();
+    assert!(rules.skips(&src.path().join("terraform.tfstate"), false));
+    assert!(rules.skips(&src.path().join("terraform.tfstate.backup"), false));
+    assert!(rules.skips(&src.path().join(".terraform"), true));
+    assert!(!rules.skips(&src.path().join(".git"), true));
+    assert!(!rules.skips(&src.path().join("main.tf"), false));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn symlinks_to_ignored_paths_are_left_out() {
+    let src = tempfile::tempdir().unwrap();
+    let src = src.path();
+    fs::write(src.join("main.tf"), "").unwrap();
+    fs::write(src.join("terraform.tfstate"), "state").unwrap();
+    fs::create_dir(src.join(".git")).unwrap();
+    fs::write(src.join(".git").join("config"), "").unwrap();
+    let link = |target: &str, name: &str| {
+      std::os::unix::fs::symlink(src.join(target), src.join(name)).unwrap()
+    };
+    link("terraform.tfstate", "state.json");
+    link(".git", "repo");
+    link(".git/config", "config");
+    link("main.tf", "linked.tf");
+    let rules = IgnoreRules::load(src, &[]).unwrap();
+    let dst = tempfile::tempdir().unwrap();
+    let dst = dst.path().join("work");
+    copy_dir_recursive(src, &dst, &rules).unwrap();
+    let mut copied: Vec<_> = fs::read_dir(&dst)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+      .collect();
+    copied.sort();
+    assert_eq!(copied, ["linked.tf", "main.tf"]);
+    let manifest = source_manifest(src, &rules).unwrap();
+    let tracked: Vec<_> = manifest.keys().collect();
+    assert_eq!(tracked, ["linked.tf", "main.tf"]);
+  }
+
+  #[test]
+  fn replace_work_dir_keeps_state_when_manifest_write_fails() {
+    let (root, work) = deployed_work_dir();
+    let err = replace_work_dir(None, &work, "manifest", |staging| {
+      // A directory in the way makes writing the manifest fail
+      fs::create_dir_all(staging.join(MANIFEST_FILE)).context("create")
+    });
+    assert!(err.is_err());
+    let state = fs::read_to_string(work.join("terraform.tfstate")).unwrap();
+    assert_eq!(state, "state");
+    assert_eq!(fs::read_to_string(work.join("main.tf")).unwrap(), "old");
+    assert_eq!(fs::read_dir(root.path()).unwrap().count(), 1);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn vars_file_is_private_even_if_left_over() {
+    use std::os::unix::fs::PermissionsExt;
+    let work = tempfile::tempdir().unwrap();
+    let path = work.path().join(VARS_FILE);
+    fs::write(&path, "{}").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    let vars = HashMap::from([("token".to_string(), "secret".to_string())]);
+    let file = write_vars_file(work.path(), &vars).unwrap();
+    let mode = fs::metadata(&file.0).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let written = fs::read_to_string(&file.0).unwrap();
+    assert_eq!(written, r#"{"token":"secret"}"#);
+  }
+
+  #[test]
+  fn replace_work_dir_leaves_no_partial_copy() {
+    let (root, work) = deployed_work_dir();
+    let fresh = root.path().join("fresh");
+    for dir in [&work, &fresh] {
+      let err = replace_work_dir(None, dir, "manifest", |staging| {
+        fs::create_dir_all(staging).context("create")?;
+        fs::write(staging.join("main.tf"), "new").context("write")?;
+        Err(io::Error::other("disk full")).context("copy")
+      });
+      assert!(err.is_err());
+    }
+    assert!(!fresh.exists());
+    assert_eq!(fs::read_to_string(work.join("main.tf")).unwrap(), "old");
+    assert_eq!(fs::read_dir(root.path()).unwrap().count(), 1);
+  }
+}